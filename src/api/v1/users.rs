@@ -1,7 +1,14 @@
 use crate::http_utils::status::Status;
 use crate::http_utils::response::api_response;
-use std::collections::HashMap;
+use crate::http_utils::types::Response;
+use crate::http_utils::request::router::PathParams;
+use crate::http_utils::query::QueryMap;
 
-pub fn handle_get_user(query_map: HashMap<String, String>) -> Vec<u8> {
-    api_response(Status::Ok, b"{\"name\": \"Stingray Get User\"}")
-}
\ No newline at end of file
+pub fn handle_get_user(_query_map: QueryMap) -> Response {
+    api_response(Status::Ok, &serde_json::json!({ "name": "Stingray Get User" }))
+}
+
+pub fn handle_get_user_by_id(params: PathParams, _query_map: QueryMap) -> Response {
+    let body = serde_json::json!({ "id": params["id"] });
+    api_response(Status::Ok, &body)
+}
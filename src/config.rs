@@ -0,0 +1,364 @@
+use crate::log::LogLevel;
+use crate::server::ServerOptions;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: http_serverrrrr [OPTIONS]
+
+Options:
+  -c, --config <FILE>              Read settings from a TOML file
+  -b, --bind <ADDR>                Address to listen on, repeat for several [default: 127.0.0.1:7878]
+      --header-timeout <SECS>      Time allowed to send the request head [default: 10]
+      --body-timeout <SECS>        Time allowed to send the request body [default: 10]
+      --keep-alive-timeout <SECS>  Idle time allowed between requests [default: 5]
+      --max-requests-per-connection <COUNT>
+                                   Requests served before closing a connection, 0 for no limit [default: 1000]
+      --max-request-line-bytes <BYTES>
+                                   Longest request line accepted [default: 8192]
+      --max-header-bytes <BYTES>   Largest request head accepted [default: 8192]
+      --max-header-count <COUNT>   Most headers accepted [default: 100]
+      --max-body-bytes <BYTES>     Largest request body accepted [default: 10485760]
+      --max-part-bytes <BYTES>     Largest part of a multipart upload accepted [default: 10485760]
+      --multipart-memory-bytes <BYTES>
+                                   Uploaded files bigger than this go to --upload-dir [default: 65536]
+      --upload-dir <DIR>           Directory uploaded files are written to [default: the system temp dir]
+      --static-root <DIR>          Directory static files are served from [default: static]
+      --read-buffer-size <BYTES>   Size of each read from a connection [default: 1024]
+      --drain-timeout <SECS>       Time open connections get to finish on shutdown [default: 30]
+      --server-header <NAME>       Value of the Server response header, empty to send none
+                                   [default: http_serverrrrr/VERSION]
+      --log-level <LEVEL>          error, warn, info or debug [default: info]
+  -h, --help                       Print this help
+
+Every option except --config can also be set in the config file, using the
+option name with underscores (max_body_bytes = 1024), or in an environment
+variable prefixed with HTTP_SERVER_ (HTTP_SERVER_MAX_BODY_BYTES=1024).
+HTTP_SERVER_CONFIG names the config file when --config is not given.
+Flags win over environment variables, which win over the config file.";
+
+// Every setting, by its config file name. Flags and environment variables are derived from it.
+const KEYS: [&str; 17] = [
+    "bind",
+    "header_timeout",
+    "body_timeout",
+    "keep_alive_timeout",
+    "max_requests_per_connection",
+    "max_request_line_bytes",
+    "max_header_bytes",
+    "max_header_count",
+    "max_body_bytes",
+    "max_part_bytes",
+    "multipart_memory_bytes",
+    "upload_dir",
+    "static_root",
+    "read_buffer_size",
+    "drain_timeout",
+    "server_header",
+    "log_level",
+];
+
+const ENV_PREFIX: &str = "HTTP_SERVER_";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind: Vec<String>,
+    pub header_timeout: Duration,
+    pub body_timeout: Duration,
+    pub keep_alive_timeout: Duration,
+    pub max_requests_per_connection: usize,
+    pub max_request_line_bytes: usize,
+    pub max_header_bytes: usize,
+    pub max_header_count: usize,
+    pub max_body_bytes: usize,
+    pub max_part_bytes: usize,
+    pub multipart_memory_bytes: usize,
+    pub upload_dir: PathBuf,
+    pub static_root: PathBuf,
+    pub read_buffer_size: usize,
+    pub drain_timeout: Duration,
+    pub server_header: Option<String>,
+    pub log_level: LogLevel,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let options = ServerOptions::default();
+        Config {
+            bind: vec!["127.0.0.1:7878".to_string()],
+            header_timeout: options.header_timeout,
+            body_timeout: options.body_timeout,
+            keep_alive_timeout: options.keep_alive_timeout,
+            max_requests_per_connection: options.max_requests_per_connection,
+            max_request_line_bytes: options.max_request_line_bytes,
+            max_header_bytes: options.max_header_bytes,
+            max_header_count: options.max_header_count,
+            max_body_bytes: options.max_body_bytes,
+            max_part_bytes: options.max_part_bytes,
+            multipart_memory_bytes: options.multipart_memory_bytes,
+            upload_dir: options.upload_dir,
+            static_root: PathBuf::from("static"),
+            read_buffer_size: options.read_buffer_size,
+            drain_timeout: options.drain_timeout,
+            server_header: options.server_header,
+            log_level: LogLevel::Info,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    // --help was passed, not really an error
+    Help,
+    Usage(String),
+    Read { path: PathBuf, message: String },
+    Parse { path: PathBuf, message: String },
+    // `origin` says where the value came from, e.g. "--bind" or "HTTP_SERVER_BIND"
+    Invalid { origin: String, value: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            ConfigError::Read { path, message } => write!(f, "could not read config file {}: {}", path.display(), message),
+            ConfigError::Parse { path, message } => write!(f, "invalid config file {}: {}", path.display(), message),
+            ConfigError::Invalid { origin, value, reason } => write!(f, "invalid value {:?} for {}: {}", value, origin, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// What the command line asked for, before it is layered over the other sources
+struct Args {
+    config: Option<PathBuf>,
+    settings: Vec<(&'static str, String, String)>,
+}
+
+fn flag_name(key: &str) -> String {
+    format!("--{}", key.replace('_', "-"))
+}
+
+fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_ascii_uppercase())
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, ConfigError> {
+    let mut parsed = Args { config: None, settings: Vec::new() };
+    let mut binds: Vec<String> = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Err(ConfigError::Help);
+        }
+        // Both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let key = match flag.as_str() {
+            "-c" | "--config" => "config",
+            "-b" => "bind",
+            _ => match KEYS.iter().find(|key| flag_name(key) == flag) {
+                Some(key) => *key,
+                None => return Err(ConfigError::Usage(format!("Unknown argument: {}", arg))),
+            },
+        };
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(ConfigError::Usage(format!("{} needs a value", flag))),
+        };
+
+        match key {
+            "config" => parsed.config = Some(PathBuf::from(value)),
+            // Repeated --bind flags add up instead of replacing each other
+            "bind" => binds.push(value),
+            _ => parsed.settings.push((key, value, flag)),
+        }
+    }
+    if !binds.is_empty() {
+        parsed.settings.insert(0, ("bind", binds.join(","), "--bind".to_string()));
+    }
+    Ok(parsed)
+}
+
+fn parse_secs(value: &str) -> Result<Duration, String> {
+    match value.trim().parse::<u64>() {
+        Ok(0) => Err("must be at least 1 second".to_string()),
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => Err("expected a whole number of seconds".to_string()),
+    }
+}
+
+fn parse_bytes(value: &str) -> Result<usize, String> {
+    value.trim().parse::<usize>().map_err(|_| "expected a number of bytes".to_string())
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    value.trim().parse::<usize>().map_err(|_| "expected a whole number".to_string())
+}
+
+// Empty for none. Line breaks would end the header early.
+fn parse_header_value(value: &str) -> Result<Option<String>, String> {
+    let value = value.trim();
+    if value.contains(['\r', '\n']) {
+        return Err("can't contain line breaks".to_string());
+    }
+    Ok(Some(value.to_string()).filter(|value| !value.is_empty()))
+}
+
+// "host:port" or "[v6]:port". The host is resolved when binding, here only the shape is checked.
+fn parse_bind(value: &str) -> Result<Vec<String>, String> {
+    let addrs: Vec<String> = value.split(',').map(|addr| addr.trim().to_string()).filter(|addr| !addr.is_empty()).collect();
+    if addrs.is_empty() {
+        return Err("expected at least one address".to_string());
+    }
+    for addr in &addrs {
+        match addr.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => return Err(format!("{:?} is not of the form host:port", addr)),
+        }
+    }
+    Ok(addrs)
+}
+
+impl Config {
+    // Defaults, then the config file, then HTTP_SERVER_* variables, then flags.
+    // `env` looks up an environment variable, so tests don't have to touch the real one.
+    pub fn load(args: impl IntoIterator<Item = String>, env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let args = parse_args(args)?;
+        let mut config = Config::default();
+
+        if let Some(path) = args.config.or_else(|| env(&env_name("config")).map(PathBuf::from)) {
+            config.apply_file(&path)?;
+        }
+        for key in KEYS {
+            let name = env_name(key);
+            if let Some(value) = env(&name) {
+                config.set(key, &value, &name)?;
+            }
+        }
+        for (key, value, flag) in &args.settings {
+            config.set(key, value, flag)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    // `load` with the process arguments and environment
+    pub fn from_env() -> Result<Config, ConfigError> {
+        Config::load(std::env::args().skip(1), |name| std::env::var(name).ok())
+    }
+
+    pub fn server_options(&self) -> ServerOptions {
+        ServerOptions {
+            header_timeout: self.header_timeout,
+            body_timeout: self.body_timeout,
+            keep_alive_timeout: self.keep_alive_timeout,
+            max_requests_per_connection: self.max_requests_per_connection,
+            max_request_line_bytes: self.max_request_line_bytes,
+            max_header_bytes: self.max_header_bytes,
+            max_header_count: self.max_header_count,
+            max_body_bytes: self.max_body_bytes,
+            max_part_bytes: self.max_part_bytes,
+            multipart_memory_bytes: self.multipart_memory_bytes,
+            upload_dir: self.upload_dir.clone(),
+            read_buffer_size: self.read_buffer_size,
+            drain_timeout: self.drain_timeout,
+            server_header: self.server_header.clone(),
+        }
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Read { path: path.to_path_buf(), message: e.to_string() })?;
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message: e.message().to_string(),
+        })?;
+
+        for (key, value) in &table {
+            let parse_error = |message: String| ConfigError::Parse { path: path.to_path_buf(), message };
+            if !KEYS.contains(&key.as_str()) {
+                return Err(parse_error(format!("unknown key `{}`", key)));
+            }
+            // Every setting is read from its text form, so the file, env and flags share one parser
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Array(items) if key == "bind" => {
+                    let addrs: Option<Vec<&str>> = items.iter().map(|item| item.as_str()).collect();
+                    match addrs {
+                        Some(addrs) => addrs.join(","),
+                        None => return Err(parse_error("`bind` must be a list of strings".to_string())),
+                    }
+                }
+                other => return Err(parse_error(format!("`{}` can't be a {}", key, other.type_str()))),
+            };
+            self.set(key, &value, &format!("`{}` in {}", key, path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str, origin: &str) -> Result<(), ConfigError> {
+        let result = match key {
+            "bind" => parse_bind(value).map(|addrs| self.bind = addrs),
+            "header_timeout" => parse_secs(value).map(|timeout| self.header_timeout = timeout),
+            "body_timeout" => parse_secs(value).map(|timeout| self.body_timeout = timeout),
+            "keep_alive_timeout" => parse_secs(value).map(|timeout| self.keep_alive_timeout = timeout),
+            "max_request_line_bytes" => parse_bytes(value).map(|bytes| self.max_request_line_bytes = bytes),
+            "max_header_bytes" => parse_bytes(value).map(|bytes| self.max_header_bytes = bytes),
+            "max_requests_per_connection" => parse_count(value).map(|count| self.max_requests_per_connection = count),
+            "max_header_count" => parse_count(value).map(|count| self.max_header_count = count),
+            "max_body_bytes" => parse_bytes(value).map(|bytes| self.max_body_bytes = bytes),
+            "max_part_bytes" => parse_bytes(value).map(|bytes| self.max_part_bytes = bytes),
+            "multipart_memory_bytes" => parse_bytes(value).map(|bytes| self.multipart_memory_bytes = bytes),
+            "upload_dir" => {
+                self.upload_dir = PathBuf::from(value);
+                Ok(())
+            }
+            "static_root" => {
+                self.static_root = PathBuf::from(value);
+                Ok(())
+            }
+            "read_buffer_size" => parse_bytes(value).map(|bytes| self.read_buffer_size = bytes),
+            "drain_timeout" => parse_secs(value).map(|timeout| self.drain_timeout = timeout),
+            "server_header" => parse_header_value(value).map(|server| self.server_header = server),
+            "log_level" => value.parse().map(|level| self.log_level = level),
+            _ => unreachable!("unknown config key {}", key),
+        };
+        result.map_err(|reason| ConfigError::Invalid { origin: origin.to_string(), value: value.to_string(), reason })
+    }
+
+    // Checks that need the final value, whichever source it came from
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, value: String, reason: &str| ConfigError::Invalid {
+            origin: key.to_string(),
+            value,
+            reason: reason.to_string(),
+        };
+        // Room for at least a request line and a Host header
+        if self.max_header_bytes < 64 {
+            return Err(invalid("max_header_bytes", self.max_header_bytes.to_string(), "must be at least 64"));
+        }
+        // A read has to make progress
+        if self.read_buffer_size == 0 {
+            return Err(invalid("read_buffer_size", self.read_buffer_size.to_string(), "must be at least 1"));
+        }
+        // "GET / HTTP/1.1"
+        if self.max_request_line_bytes < 14 {
+            return Err(invalid("max_request_line_bytes", self.max_request_line_bytes.to_string(), "must be at least 14"));
+        }
+        if !self.static_root.is_dir() {
+            return Err(invalid("static_root", self.static_root.display().to_string(), "not a directory"));
+        }
+        if !self.upload_dir.is_dir() {
+            return Err(invalid("upload_dir", self.upload_dir.display().to_string(), "not a directory"));
+        }
+        Ok(())
+    }
+}
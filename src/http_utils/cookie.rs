@@ -0,0 +1,138 @@
+use crate::http_utils::date::http_date;
+use crate::http_utils::parser::is_token_byte;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+// A cookie to set with `Response::cookie`, written as a `Set-Cookie` value
+// (RFC 6265 section 4.1). Attributes are left out unless they are set. Bytes
+// the grammar doesn't allow, such as a `;` that would start another attribute,
+// are written percent-encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // Tells the client to drop the cookie named `name` right away
+    pub fn removal(name: impl Into<String>) -> Self {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", encode(&self.name, is_token_byte), encode(&self.value, is_cookie_octet))?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", encode(path, is_attribute_octet))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", encode(domain, is_attribute_octet))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict")?,
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax")?,
+            Some(SameSite::None) => write!(f, "; SameSite=None")?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+// Any US-ASCII but controls, whitespace, `"`, `,`, `;` and `\`. `%` is left out
+// too, so a value that had to be encoded can be decoded again.
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x24 | 0x26..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+// Any character but controls and `;`
+fn is_attribute_octet(b: u8) -> bool {
+    (0x20..0x7F).contains(&b) && b != b';'
+}
+
+fn encode(text: &str, allowed: fn(u8) -> bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if allowed(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
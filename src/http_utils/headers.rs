@@ -0,0 +1,96 @@
+// Header fields in the order they were added. Names are compared
+// case-insensitively (RFC 9110 section 5.1) but keep the case they were
+// written with, and a name can hold several values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap { entries: Vec::new() }
+    }
+
+    // First value for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Checks a comma separated header such as `Connection` for a token, ignoring case
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    // Replaces every existing value for `name`, keeping the position of the first one
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        match self.entries.iter().position(|(key, _)| key.eq_ignore_ascii_case(&name)) {
+            Some(first) => {
+                let mut index = 0;
+                self.entries.retain(|(key, _)| {
+                    let keep = index <= first || !key.eq_ignore_ascii_case(&name);
+                    index += 1;
+                    keep
+                });
+                self.entries[first] = (name, value.into());
+            }
+            None => self.entries.push((name, value.into())),
+        }
+    }
+
+    // Adds a value without touching existing ones, e.g. for `Set-Cookie`
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        self.entries.retain(|(key, value)| {
+            if key.eq_ignore_ascii_case(name) {
+                removed.push(value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = HeaderMap::new();
+        for (name, value) in iter {
+            headers.append(name, value);
+        }
+        headers
+    }
+}
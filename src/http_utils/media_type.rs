@@ -0,0 +1,158 @@
+use crate::http_utils::parser::is_token_byte;
+use crate::http_utils::status::ParseError;
+use std::fmt;
+
+// A parsed `Content-Type` such as `application/problem+json; charset=UTF-8`
+// (RFC 9110 section 8.3.1). Type, subtype and parameter names are lowercased,
+// since they compare case-insensitively, parameter values are kept as sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    pub kind: String,
+    pub subtype: String,
+    // The structured syntax suffix, `json` in `problem+json` (RFC 6838 section 4.2.8)
+    pub suffix: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn parse(value: &str) -> Result<Self, ParseError> {
+        let invalid = || ParseError::InvalidHeader(format!("invalid media type {:?}", value));
+        let mut rest = value;
+        let essence = take_until_semicolon(&mut rest);
+        let (kind, subtype) = essence.trim().split_once('/').ok_or_else(invalid)?;
+        if !is_token(kind) || !is_token(subtype) {
+            return Err(invalid());
+        }
+        let kind = kind.to_ascii_lowercase();
+        let subtype = subtype.to_ascii_lowercase();
+        let suffix = subtype.rsplit_once('+').map(|(_, suffix)| suffix.to_string());
+
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once('=').ok_or_else(invalid)?;
+            let name = name.trim();
+            if !is_token(name) {
+                return Err(invalid());
+            }
+            rest = after;
+            let value = match rest.strip_prefix('"') {
+                Some(quoted) => {
+                    let (value, after) = quoted_string(quoted).ok_or_else(invalid)?;
+                    rest = after;
+                    // Only whitespace may follow the closing quote
+                    if !take_until_semicolon(&mut rest).trim().is_empty() {
+                        return Err(invalid());
+                    }
+                    value
+                }
+                None => take_until_semicolon(&mut rest).trim().to_string(),
+            };
+            params.push((name.to_ascii_lowercase(), value));
+        }
+        Ok(MediaType { kind, subtype, suffix, params })
+    }
+
+    // `type/subtype` without the parameters
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.kind, self.subtype)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    // `application/json` and every `+json` type
+    pub fn is_json(&self) -> bool {
+        (self.kind == "application" && self.subtype == "json") || self.suffix.as_deref() == Some("json")
+    }
+
+    // Whether this type is one `pattern` allows. A pattern can use `*` for the type
+    // or subtype, and `*+suffix` for every subtype with that suffix. Parameters
+    // of the pattern are ignored.
+    pub fn matches(&self, pattern: &MediaType) -> bool {
+        let kind_matches = pattern.kind == "*" || pattern.kind == self.kind;
+        let subtype_matches = match pattern.subtype.strip_prefix("*+") {
+            Some(suffix) => self.suffix.as_deref() == Some(suffix),
+            None => pattern.subtype == "*" || pattern.subtype == self.subtype,
+        };
+        kind_matches && subtype_matches
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.subtype)?;
+        for (name, value) in &self.params {
+            if !value.is_empty() && is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns what is before the next `;` and moves `rest` past it
+fn take_until_semicolon<'a>(rest: &mut &'a str) -> &'a str {
+    let (taken, after) = rest.split_once(';').unwrap_or((rest, ""));
+    *rest = after;
+    taken
+}
+
+// The content of a quoted-string whose opening quote is already gone, and what follows it
+fn quoted_string(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '"' => return Some((value, &input[i + 1..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+fn is_token(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(is_token_byte)
+}
+
+// Decodes a text body sent in `charset`, UTF-8 when none is given. UTF-8, ASCII,
+// Latin-1 and UTF-16 are understood, any other charset is refused with a 415.
+pub fn decode_text(body: &[u8], charset: Option<&str>) -> Result<String, ParseError> {
+    let charset = charset.unwrap_or("utf-8").to_ascii_lowercase();
+    let not_in = |charset: &str| ParseError::MalformedRequest(format!("body is not valid {}", charset));
+    match charset.as_str() {
+        "utf-8" | "utf8" => String::from_utf8(body.to_vec()).map_err(|_| not_in("UTF-8")),
+        "us-ascii" | "ascii" => match body.is_ascii() {
+            true => Ok(String::from_utf8_lossy(body).into_owned()),
+            false => Err(not_in("US-ASCII")),
+        },
+        // Every byte is the code point of the same value
+        "iso-8859-1" | "latin1" | "l1" => Ok(body.iter().map(|&b| b as char).collect()),
+        "utf-16le" | "utf-16be" | "utf-16" => {
+            if !body.len().is_multiple_of(2) {
+                return Err(not_in("UTF-16"));
+            }
+            // Without a byte order, a BOM decides, and big endian is the default (RFC 2781 section 4.3)
+            let (little_endian, body) = match (charset.as_str(), body) {
+                ("utf-16le", body) => (true, body),
+                ("utf-16be", body) => (false, body),
+                (_, [0xFF, 0xFE, rest @ ..]) => (true, rest),
+                (_, [0xFE, 0xFF, rest @ ..]) => (false, rest),
+                (_, body) => (false, body),
+            };
+            let units: Vec<u16> = body
+                .chunks_exact(2)
+                .map(|pair| if little_endian { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+                .collect();
+            String::from_utf16(&units).map_err(|_| not_in("UTF-16"))
+        }
+        other => Err(ParseError::UnsupportedMediaType(format!("charset {} is not supported", other))),
+    }
+}
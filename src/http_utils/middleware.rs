@@ -0,0 +1,97 @@
+use crate::http_utils::parser::RequestHead;
+use crate::http_utils::request::handler::{BoxFuture, Handler, HandlerResult, Request};
+use crate::http_utils::types::Response;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+// Runs around a handler. A middleware can change the request before passing it
+// on with `next.run(request)`, answer by itself without calling `next`, or
+// change the response on the way back.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<HandlerResult>;
+
+    // Sees a request sent with `Expect: 100-continue` once only its head is in,
+    // and can answer it before the client is told to send the body, e.g. with a
+    // 401. That answer goes out as is, without the rest of the chain. `None` lets
+    // the body come, and `handle` still runs once it is read.
+    fn check_head(&self, _head: &RequestHead) -> Option<Response> {
+        None
+    }
+}
+
+// The first answer a middleware in `stack` gives to `head`, outermost first
+pub fn check_head(stack: &MiddlewareStack, head: &RequestHead) -> Option<Response> {
+    stack.iter().find_map(|middleware| middleware.check_head(head))
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<HandlerResult> {
+        Box::pin(self(request, next))
+    }
+}
+
+pub type MiddlewareStack = Arc<Vec<Arc<dyn Middleware>>>;
+
+// The rest of the chain: the middleware not run yet, then the handler
+pub struct Next {
+    stack: MiddlewareStack,
+    index: usize,
+    endpoint: Arc<dyn Handler>,
+}
+
+impl Next {
+    pub fn new(stack: MiddlewareStack, endpoint: Arc<dyn Handler>) -> Self {
+        Next { stack, index: 0, endpoint }
+    }
+
+    pub fn run(self, request: Request) -> BoxFuture<HandlerResult> {
+        match self.stack.get(self.index).cloned() {
+            Some(middleware) => {
+                let next = Next { stack: self.stack, index: self.index + 1, endpoint: self.endpoint };
+                middleware.handle(request, next)
+            }
+            None => self.endpoint.call(request),
+        }
+    }
+}
+
+// A handler with a fixed middleware stack in front of it, used for nested routers
+pub struct Layered {
+    pub stack: MiddlewareStack,
+    pub endpoint: Arc<dyn Handler>,
+}
+
+impl Handler for Layered {
+    fn call(&self, request: Request) -> BoxFuture<HandlerResult> {
+        Next::new(self.stack.clone(), self.endpoint.clone()).run(request)
+    }
+
+    fn check_head(&self, head: &RequestHead) -> Option<Response> {
+        check_head(&self.stack, head).or_else(|| self.endpoint.check_head(head))
+    }
+}
+
+// Logs every request and the response it got
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<HandlerResult> {
+        Box::pin(async move {
+            let started = Instant::now();
+            let summary = format!("{} {} from {}", request.method, request.path, request.remote_addr);
+            log_debug!("FULL Parsed Request: {:?}", request);
+
+            let result = next.run(request).await;
+            match &result {
+                Ok(response) => log_info!("{} -> {} in {:?}", summary, response.status.code(), started.elapsed()),
+                Err(e) => log_info!("{} -> {:?} in {:?}", summary, e, started.elapsed()),
+            }
+            result
+        })
+    }
+}
@@ -2,4 +2,10 @@ pub mod status;
 pub mod response;
 pub mod request;
 pub mod parser;
-pub mod types;
\ No newline at end of file
+pub mod types;
+pub mod headers;
+pub mod query;
+pub mod media_type;
+pub mod cookie;
+pub mod date;
+pub mod middleware;
\ No newline at end of file
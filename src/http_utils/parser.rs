@@ -1,129 +1,396 @@
-use crate::http_utils::types::{ApiRequest, HttpRequest, UniversalBody, ParsedRequest};
+use crate::http_utils::types::{ApiRequest, HttpRequest, UniversalBody, ParsedRequest, RawRequest};
 use crate::http_utils::status::ParseError;
-use std::collections::HashMap;
+use crate::http_utils::headers::HeaderMap;
+use crate::http_utils::query::QueryMap;
+use crate::http_utils::media_type::{decode_text, MediaType};
+use std::ops::Range;
 
-pub fn parse_request_line(line: &str) -> Result<(String, String, String), ParseError> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.len() != 3 {
-        return Err(ParseError::MalformedRequest);
+pub const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+pub fn parse_request_line(line: &str) -> Result<(&str, &str, &str), ParseError> {
+    let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+    if parts.len() != 3 || !METHODS.contains(&parts[0]) {
+        return Err(ParseError::BadRequestLine(line.to_string()));
+    }
+    // `HTTP/1.x` is all we speak, later minor versions are answered as 1.1 (RFC 9110 section 6.2)
+    if !is_http_version(parts[2]) {
+        return Err(ParseError::BadRequestLine(line.to_string()));
+    }
+    if !parts[2].starts_with("HTTP/1.") {
+        return Err(ParseError::UnsupportedVersion(parts[2].to_string()));
+    }
+    Ok((parts[0], parts[1], parts[2]))
+}
+
+// `HTTP/` followed by a single digit major and minor version (RFC 9112 section 2.3)
+fn is_http_version(version: &str) -> bool {
+    match version.strip_prefix("HTTP/").map(str::as_bytes) {
+        Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+// A request head parsed in place: every field borrows from the buffer it was read into
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestHead<'a> {
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+    pub headers: Vec<(&'a str, &'a str)>,
+    // Bytes taken by the head, including the blank line ending it. The body starts here.
+    pub len: usize,
+}
+
+impl<'a> RequestHead<'a> {
+    // First value of a header, names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.header_values(name).next()
+    }
+
+    pub fn header_values(&self, name: &str) -> impl Iterator<Item = &'a str> {
+        let name = name.to_string();
+        self.headers.iter().filter(move |(key, _)| key.eq_ignore_ascii_case(&name)).map(|(_, value)| *value)
+    }
+
+    // No Content-Length means no body, whatever the method (RFC 9112 section 6.3).
+    // Repeated fields or a list are only accepted when every value is the same.
+    pub fn content_length(&self) -> Result<usize, ParseError> {
+        let mut length = None;
+        for value in self.header_values("Content-Length").flat_map(|values| values.split(',')) {
+            let value = value.trim_matches([' ', '\t']);
+            // `usize::from_str` would also take "+5"
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::InvalidContentLength);
+            }
+            let value = value.parse::<usize>().map_err(|_| ParseError::InvalidContentLength)?;
+            if length.is_some_and(|length| length != value) {
+                return Err(ParseError::ConflictingContentLength);
+            }
+            length = Some(value);
+        }
+        Ok(length.unwrap_or(0))
     }
-    let method = parts[0].to_string();
-    let path = parts[1].to_string();
-    let version = parts[2].to_string();
-    Ok((method, path, version))
+
+    // Whether the last transfer coding is chunked
+    pub fn is_chunked(&self) -> bool {
+        self.header_values("Transfer-Encoding")
+            .last()
+            .and_then(|codings| codings.rsplit(',').next())
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    }
+
+    // Only HTTP/1.1 clients wait for a 100 Continue, HTTP/1.0 ones must be ignored (RFC 9110 section 10.1.1)
+    pub fn expects_continue(&self) -> bool {
+        self.version != "HTTP/1.0" && self.header("Expect").is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    }
+
+    // Refuses every head whose body length could be read two ways. Chunked is the
+    // only transfer coding we decode, so it has to be the only one.
+    pub fn check_framing(&self) -> Result<(), ParseError> {
+        self.content_length()?;
+        let mut codings = self.header_values("Transfer-Encoding").flat_map(|values| values.split(',')).peekable();
+        if codings.peek().is_none() {
+            return Ok(());
+        }
+        if self.header("Content-Length").is_some() {
+            return Err(ParseError::ContentLengthWithTransferEncoding);
+        }
+        let codings: Vec<&str> = codings.map(|coding| coding.trim_matches([' ', '\t'])).collect();
+        if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") || self.version == "HTTP/1.0" {
+            return Err(ParseError::UnsupportedTransferEncoding);
+        }
+        Ok(())
+    }
+
+    pub fn header_map(&self) -> HeaderMap {
+        self.headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HeadState {
+    RequestLine,
+    Headers,
+    Done,
+}
+
+// Parses a request head as it comes in. Feed it the same buffer again each time
+// more bytes are appended: it picks up where it stopped instead of rescanning,
+// and only records where each field is, so nothing is copied. Lines may end in
+// CRLF or a bare LF (RFC 9112 section 2.2).
+#[derive(Debug, Clone)]
+pub struct HeadParser {
+    state: HeadState,
+    // Start of the line being read, and how far into the buffer has been scanned
+    line_start: usize,
+    scanned: usize,
+    method: Range<usize>,
+    target: Range<usize>,
+    version: Range<usize>,
+    headers: Vec<(Range<usize>, Range<usize>)>,
+    max_request_line_bytes: usize,
+    max_header_bytes: usize,
+    max_header_count: usize,
 }
 
-pub fn parse_headers(lines: &[&str]) -> Result<HashMap<String, String>, ParseError> {
-    let mut headers = HashMap::new();
-    for line in lines {
-        let header_parts: Vec<&str> = line.splitn(2, ": ").collect();
-        if header_parts.len() == 2 {
-            headers.insert(header_parts[0].to_string(), header_parts[1].trim().to_string());
+impl Default for HeadParser {
+    fn default() -> Self {
+        HeadParser {
+            state: HeadState::RequestLine,
+            line_start: 0,
+            scanned: 0,
+            method: 0..0,
+            target: 0..0,
+            version: 0..0,
+            headers: Vec::new(),
+            max_request_line_bytes: usize::MAX,
+            max_header_bytes: usize::MAX,
+            max_header_count: usize::MAX,
         }
     }
-    Ok(headers)
 }
 
+// tchar from RFC 9110 section 5.6.2, what a header name is made of
+pub(crate) fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Where `part` sits inside `whole`, which it was sliced from
+fn span_of(whole: &str, offset: usize, part: &str) -> Range<usize> {
+    let start = offset + (part.as_ptr() as usize - whole.as_ptr() as usize);
+    start..start + part.len()
+}
+
+fn str_at<'a>(buffer: &'a [u8], span: &Range<usize>) -> &'a str {
+    // Every span was checked to be UTF-8 when it was recorded
+    std::str::from_utf8(&buffer[span.clone()]).unwrap_or_default()
+}
+
+impl HeadParser {
+    pub fn new() -> Self {
+        HeadParser::default()
+    }
+
+    // Over-long request lines fail with `RequestLineTooLong`, heads over
+    // `max_header_bytes` or with too many headers with `HeadersTooLarge`.
+    // Checked as bytes arrive, so an oversized head is refused without waiting for the rest.
+    pub fn with_limits(mut self, max_request_line_bytes: usize, max_header_bytes: usize, max_header_count: usize) -> Self {
+        self.max_request_line_bytes = max_request_line_bytes;
+        self.max_header_bytes = max_header_bytes;
+        self.max_header_count = max_header_count;
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == HeadState::Done
+    }
+
+    // `Ok(None)` until the blank line ending the head is in `buffer`
+    pub fn parse<'a>(&mut self, buffer: &'a [u8]) -> Result<Option<RequestHead<'a>>, ParseError> {
+        while self.state != HeadState::Done {
+            let Some(offset) = buffer[self.scanned..].iter().position(|&b| b == b'\n') else {
+                self.scanned = buffer.len();
+                if self.state == HeadState::RequestLine && buffer.len() - self.line_start > self.max_request_line_bytes {
+                    return Err(ParseError::RequestLineTooLong);
+                }
+                if buffer.len() > self.max_header_bytes {
+                    return Err(ParseError::HeadersTooLarge);
+                }
+                return Ok(None);
+            };
 
-pub fn trim_by_content_length(headers: HashMap<String, String>, buffer: &[u8], body_start: Option<usize>, method: &str) -> Result<Vec<u8>, ParseError> {
-    if headers.get("Content-Length").is_none() && method != "POST" {
-        return Ok(Vec::new());
+            let line_end = self.scanned + offset;
+            let mut line = self.line_start..line_end;
+            if buffer[line.clone()].last() == Some(&b'\r') {
+                line.end -= 1;
+            }
+            self.scanned = line_end + 1;
+            self.line_start = self.scanned;
+            // An over-long request line is a 414 even when it also fills the head
+            if self.state == HeadState::RequestLine && line.len() > self.max_request_line_bytes {
+                return Err(ParseError::RequestLineTooLong);
+            }
+            if self.scanned > self.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+
+            match self.state {
+                // Empty lines before the request line are skipped (RFC 9112 section 2.2)
+                HeadState::RequestLine if line.is_empty() => {}
+                HeadState::RequestLine => {
+                    self.parse_request_line(buffer, line)?;
+                    self.state = HeadState::Headers;
+                }
+                HeadState::Headers if line.is_empty() => {
+                    self.state = HeadState::Done;
+                    let head = self.head(buffer);
+                    if let Some(head) = &head {
+                        head.check_framing()?;
+                    }
+                    return Ok(head);
+                }
+                HeadState::Headers => {
+                    self.parse_header_line(buffer, line)?;
+                    if self.headers.len() > self.max_header_count {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                }
+                HeadState::Done => unreachable!(),
+            }
+        }
+        Ok(self.head(buffer))
     }
 
-    let content_length = headers.get("Content-Length").ok_or(ParseError::MalformedRequest)?;
-    let content_length = content_length.parse::<usize>().map_err(|_| ParseError::MalformedRequest)?;
-    let start = body_start.unwrap_or(0);
-    let end = start + content_length;
-    Ok(buffer[start..end].to_vec())
+    // The parsed head again, without rescanning. `None` until `parse` has seen all of it.
+    pub fn head<'a>(&self, buffer: &'a [u8]) -> Option<RequestHead<'a>> {
+        if self.state != HeadState::Done {
+            return None;
+        }
+        Some(RequestHead {
+            method: str_at(buffer, &self.method),
+            target: str_at(buffer, &self.target),
+            version: str_at(buffer, &self.version),
+            headers: self.headers.iter().map(|(name, value)| (str_at(buffer, name), str_at(buffer, value))).collect(),
+            len: self.scanned,
+        })
+    }
+
+    fn parse_request_line(&mut self, buffer: &[u8], line: Range<usize>) -> Result<(), ParseError> {
+        let text = std::str::from_utf8(&buffer[line.clone()])
+            .map_err(|_| ParseError::BadRequestLine(String::from_utf8_lossy(&buffer[line.clone()]).into_owned()))?;
+        let (method, target, version) = parse_request_line(text)?;
+        self.method = span_of(text, line.start, method);
+        self.target = span_of(text, line.start, target);
+        self.version = span_of(text, line.start, version);
+        Ok(())
+    }
+
+    fn parse_header_line(&mut self, buffer: &[u8], line: Range<usize>) -> Result<(), ParseError> {
+        let text = std::str::from_utf8(&buffer[line.clone()])
+            .map_err(|_| ParseError::InvalidHeader(format!("{:?} is not UTF-8", String::from_utf8_lossy(&buffer[line.clone()]))))?;
+        let (name, value) = parse_field_line(text)?;
+        self.headers.push((span_of(text, line.start, name), span_of(text, line.start, value)));
+        Ok(())
+    }
 }
 
-pub fn get_content_length(buffer: &[u8]) -> Result<usize, ParseError> {
-    let request_str = std::str::from_utf8(buffer).map_err(|_| ParseError::MalformedRequest)?;
-    println!("Request String: {}", request_str);
-    let lines: Vec<&str> = request_str.split('\n').collect();
-    let headers = parse_headers(&lines[1..])?;
-    let binding = "0".to_string();
-    let content_length = headers.get("Content-Length").unwrap_or(&binding);
-    Ok(content_length.parse::<usize>().map_err(|_| ParseError::MalformedRequest)?)
+// One `name: value` line of a head or a chunked trailer section, into its name and
+// trimmed value. Whitespace around the value is optional (RFC 9110 section 5.6.3),
+// but not around the name (RFC 9112 section 5.1), and continuation lines are
+// refused rather than unfolded (RFC 9112 section 5.2)
+pub(crate) fn parse_field_line(text: &str) -> Result<(&str, &str), ParseError> {
+    if text.starts_with([' ', '\t']) {
+        return Err(ParseError::ObsoleteLineFolding);
+    }
+    let (name, value) = text.split_once(':').ok_or_else(|| ParseError::InvalidHeader(format!("no colon in {:?}", text)))?;
+    if name.ends_with([' ', '\t']) {
+        return Err(ParseError::WhitespaceBeforeColon);
+    }
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return Err(ParseError::InvalidHeader(format!("bad name {:?}", name)));
+    }
+    Ok((name, value.trim_matches([' ', '\t'])))
 }
 
-fn parse_request(buffer: &[u8]) -> Result<(String, String, String, HashMap<String, String>, UniversalBody), ParseError> {
-    let request_str = std::str::from_utf8(buffer).map_err(|_| ParseError::MalformedRequest)?;
-    
-    // Split into lines
-    let lines: Vec<&str> = request_str.split('\n').collect();
-    
-    // Parse request line (first line)
-    let request_line = lines.first().ok_or(ParseError::MalformedRequest)?;
-    let (method, path, version) = parse_request_line(request_line)?;
-    
-    // Parse headers
-    let headers = parse_headers(&lines[1..])?;
+type RequestParts = (String, String, String, HeaderMap, HeaderMap, UniversalBody);
 
-    let header_end = buffer.windows(4).position(|window| window == b"\r\n\r\n");
-    let body_start = header_end.map(|pos| pos + 4);
+fn parse_request(request: &mut RawRequest) -> Result<RequestParts, ParseError> {
+    let multipart = request.multipart.take();
+    let head = request.head();
+    let mut headers = head.header_map();
 
-    let body = trim_by_content_length(headers.clone(), buffer, body_start, method.as_str())?;
-    let body = deserialize_body(&body, headers.get("Content-Type").map_or("text/plain", |v| v))?;
-    Ok((method, path, version, headers, body))
+    // A dechunked body is handed on like any other Content-Length framed one. Its
+    // trailer fields stay apart, they may not be merged into the headers (RFC 9110 section 6.5.1).
+    let mut trailers = HeaderMap::new();
+    for (name, value) in &request.trailers {
+        trailers.append(name, value);
+    }
+    if head.is_chunked() {
+        headers.remove("Transfer-Encoding");
+        headers.remove("Content-Length");
+        // A multipart body wasn't kept, so there is no length to give
+        if multipart.is_none() {
+            headers.insert("Content-Length", request.body.len().to_string());
+        }
+    }
+
+    // Multipart bodies were parsed as they were read, so uploads didn't have to fit in memory
+    let body = match multipart {
+        Some(multipart) => UniversalBody::Multipart(multipart),
+        None => deserialize_body(&request.body, headers.get("Content-Type").unwrap_or("text/plain"))?,
+    };
+    Ok((head.method.to_string(), head.target.to_string(), head.version.to_string(), headers, trailers, body))
 }
 
-pub fn parse_web_request(buffer: &[u8]) -> Result<HttpRequest, ParseError> {
-    let (method, path, version, headers, body) = parse_request(buffer)?;
+pub fn parse_web_request(request: &mut RawRequest) -> Result<HttpRequest, ParseError> {
+    let (method, path, version, headers, trailers, body) = parse_request(request)?;
     Ok(HttpRequest {
         method,
         path,
         version,
         headers,
+        trailers,
         body,
     })
 }
 
-pub fn parse_api_request(buffer: &[u8]) -> Result<ApiRequest, ParseError> {
-    let (method, path, version, headers, body) = parse_request(buffer)?;
+pub fn parse_api_request(request: &mut RawRequest) -> Result<ApiRequest, ParseError> {
+    let (method, path, version, headers, trailers, body) = parse_request(request)?;
     Ok(ApiRequest {
         method,
         path,
         version,
         headers,
+        trailers,
         body,
     })
 }
 
 
+// A `Content-Type` that doesn't parse is treated like an unknown type. Multipart bodies
+// aren't handled here, they are parsed while being read with the server's limits.
 pub fn deserialize_body(body: &[u8], content_type: &str) -> Result<UniversalBody, ParseError> {
-    match content_type {
-        "application/json" => {
-            println!("JSON: {:?}", body);
-            let res = serde_json::from_slice(body)
+    let Ok(media_type) = MediaType::parse(content_type) else {
+        return Ok(text_or_binary(body));
+    };
+    match (media_type.kind.as_str(), media_type.subtype.as_str()) {
+        _ if media_type.is_json() => {
+            log_debug!("JSON: {:?}", body);
+            // JSON is UTF-8 (RFC 8259 section 8.1), a charset is only honoured for older clients
+            let text = decode_text(body, media_type.charset())?;
+            serde_json::from_str(&text)
                 .map(UniversalBody::Json)
-                .map_err(|_| ParseError::MalformedRequest);
-            res
-        }
-        "text/plain" | "application/x-www-form-urlencoded" => {
-            String::from_utf8(body.to_vec())
-                .map(UniversalBody::Text)
-                .map_err(|_| ParseError::MalformedRequest)
+                .map_err(|e| ParseError::MalformedRequest(format!("invalid JSON body: {}", e)))
         }
-        "application/octet-stream" => Ok(UniversalBody::Binary(body.to_vec())),
-        _ => {
-            String::from_utf8(body.to_vec())
-                .map(UniversalBody::Text)
-                .map_err(|_| ParseError::MalformedRequest)
+        // Encoded like a query string (HTML section 4.10.21.7)
+        ("application", "x-www-form-urlencoded") => {
+            let text = decode_text(body, media_type.charset())?;
+            QueryMap::parse(&text).map(UniversalBody::Form)
         }
+        ("text", _) => decode_text(body, media_type.charset()).map(UniversalBody::Text),
+        ("application", "octet-stream") => Ok(UniversalBody::Binary(body.to_vec())),
+        _ => Ok(text_or_binary(body)),
+    }
+}
+
+// Unknown types are passed on as text when they are text, raw otherwise
+fn text_or_binary(body: &[u8]) -> UniversalBody {
+    match String::from_utf8(body.to_vec()) {
+        Ok(text) => UniversalBody::Text(text),
+        Err(e) => UniversalBody::Binary(e.into_bytes()),
     }
 }
 
-pub fn parse_request_by_type(is_api: bool, buffer: &[u8]) -> Result<ParsedRequest, ParseError> {
+pub fn parse_request_by_type(is_api: bool, request: &mut RawRequest) -> Result<ParsedRequest, ParseError> {
 
     if is_api {
-        match parse_api_request(buffer) {
+        match parse_api_request(request) {
             Ok(req) => Ok(ParsedRequest::Api(req)),
             Err(e) => Err(e),
         }
     } else {
-        match parse_web_request(buffer) {
-            Ok(req) => Ok(ParsedRequest::HTTP(req)),
+        match parse_web_request(request) {
+            Ok(req) => Ok(ParsedRequest::Http(req)),
             Err(e) => Err(e),
         }
     }
@@ -0,0 +1,87 @@
+use crate::http_utils::status::ParseError;
+use serde::de::DeserializeOwned;
+
+// Decodes `%XX` escapes (RFC 3986 section 2.1). A `%` not followed by two hex
+// digits is kept as it is, like browsers do. In a query `+` also stands for a
+// space (application/x-www-form-urlencoded), in a path it is just a `+`.
+pub fn percent_decode(input: &str, plus_as_space: bool) -> Result<String, ParseError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes.get(i + 1).and_then(|&b| hex_value(b)), bytes.get(i + 2).and_then(|&b| hex_value(b)));
+        if bytes[i] == b'%'
+            && let (Some(high), Some(low)) = escaped {
+            decoded.push(high * 16 + low);
+            i += 3;
+            continue;
+        }
+        decoded.push(if bytes[i] == b'+' && plus_as_space { b' ' } else { bytes[i] });
+        i += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| ParseError::MalformedRequest(format!("{:?} does not decode to UTF-8", input)))
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|digit| digit as u8)
+}
+
+// The decoded `key=value` pairs of a query string, in order. A key can appear
+// several times (`?tag=a&tag=b`), and a key without `=` has an empty value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryMap {
+    raw: String,
+    entries: Vec<(String, String)>,
+}
+
+impl QueryMap {
+    pub fn new() -> Self {
+        QueryMap::default()
+    }
+
+    // `query` is everything after the `?`, still encoded
+    pub fn parse(query: &str) -> Result<Self, ParseError> {
+        let mut entries = Vec::new();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            entries.push((percent_decode(key, true)?, percent_decode(value, true)?));
+        }
+        Ok(QueryMap { raw: query.to_string(), entries })
+    }
+
+    // First value for `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter().filter(move |(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // As it was received, still encoded
+    pub fn as_encoded(&self) -> &str {
+        &self.raw
+    }
+
+    // The query as a `T`, with values parsed into the field types. A field holds
+    // one value, so a repeated key is refused here and left to `get_all`.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, ParseError> {
+        serde_urlencoded::from_str(&self.raw).map_err(|e| ParseError::MalformedRequest(format!("invalid query: {}", e)))
+    }
+}
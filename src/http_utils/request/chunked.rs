@@ -0,0 +1,167 @@
+use crate::http_utils::parser::parse_field_line;
+use crate::http_utils::status::ParseError;
+
+// Longest chunk-size or trailer line we are willing to buffer
+const MAX_LINE_LENGTH: usize = 8192;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+// Incremental decoder for `Transfer-Encoding: chunked` bodies (RFC 9112 section 7.1).
+// Bytes can be fed in whatever pieces they arrive from the socket.
+pub struct ChunkedDecoder {
+    state: State,
+    line: Vec<u8>,
+    body: Vec<u8>,
+    body_len: usize,
+    trailers: Vec<(String, String)>,
+    // Bytes of trailer section read so far
+    trailer_bytes: usize,
+    max_trailer_bytes: usize,
+    max_trailer_count: usize,
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkedDecoder {
+    pub fn new() -> Self {
+        ChunkedDecoder {
+            state: State::Size,
+            line: Vec::new(),
+            body: Vec::new(),
+            body_len: 0,
+            trailers: Vec::new(),
+            trailer_bytes: 0,
+            max_trailer_bytes: usize::MAX,
+            max_trailer_count: usize::MAX,
+        }
+    }
+
+    // Trailers are header fields sent late, so they get what is left of the head
+    // limits. Going over either is refused with `HeadersTooLarge`.
+    pub fn with_limits(mut self, max_trailer_bytes: usize, max_trailer_count: usize) -> Self {
+        self.max_trailer_bytes = max_trailer_bytes;
+        self.max_trailer_count = max_trailer_count;
+        self
+    }
+
+    // Bytes of chunk data decoded so far, including any already taken
+    pub fn body_len(&self) -> usize {
+        self.body_len
+    }
+
+    // Hands over the data decoded since the last call, for bodies that are
+    // processed as they arrive instead of kept whole
+    pub fn take_body(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.body)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Returns how many bytes of `input` were consumed. Anything after the end
+    // of the chunked body is left untouched.
+    pub fn feed(&mut self, input: &[u8]) -> Result<usize, ParseError> {
+        let mut pos = 0;
+        while pos < input.len() && self.state != State::Done {
+            match self.state {
+                State::Data(remaining) => {
+                    let take = remaining.min(input.len() - pos);
+                    self.body.extend_from_slice(&input[pos..pos + take]);
+                    self.body_len += take;
+                    pos += take;
+                    self.state = if take == remaining { State::DataEnd } else { State::Data(remaining - take) };
+                }
+                _ => {
+                    let (line_done, used) = self.take_line(&input[pos..])?;
+                    pos += used;
+                    if line_done {
+                        self.finish_line()?;
+                    }
+                }
+            }
+        }
+        Ok(pos)
+    }
+
+    pub fn into_parts(self) -> (Vec<u8>, Vec<(String, String)>) {
+        (self.body, self.trailers)
+    }
+
+    fn take_line(&mut self, input: &[u8]) -> Result<(bool, usize), ParseError> {
+        let (done, used) = match input.iter().position(|&b| b == b'\n') {
+            Some(i) => (true, i + 1),
+            None => (false, input.len()),
+        };
+        self.line.extend_from_slice(&input[..used]);
+        if self.state == State::Trailer && self.trailer_bytes + self.line.len() > self.max_trailer_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        if self.line.len() > MAX_LINE_LENGTH {
+            return Err(ParseError::MalformedRequest(format!("chunk line over {} bytes", MAX_LINE_LENGTH)));
+        }
+        Ok((done, used))
+    }
+
+    fn finish_line(&mut self) -> Result<(), ParseError> {
+        let line = std::mem::take(&mut self.line);
+        if self.state == State::Trailer {
+            self.trailer_bytes += line.len();
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        match self.state {
+            State::Size => {
+                let size = parse_chunk_size(line)?;
+                self.state = if size == 0 { State::Trailer } else { State::Data(size) };
+            }
+            State::DataEnd => {
+                if !line.is_empty() {
+                    return Err(ParseError::MalformedRequest("chunk data longer than its size".to_string()));
+                }
+                self.state = State::Size;
+            }
+            State::Trailer => {
+                if line.is_empty() {
+                    self.state = State::Done;
+                } else {
+                    let line = std::str::from_utf8(line).map_err(|_| ParseError::InvalidHeader("trailer is not UTF-8".to_string()))?;
+                    let (name, value) = parse_field_line(line)?;
+                    self.trailers.push((name.to_string(), value.to_string()));
+                    if self.trailers.len() > self.max_trailer_count {
+                        return Err(ParseError::HeadersTooLarge);
+                    }
+                }
+            }
+            State::Data(_) | State::Done => unreachable!("line parsing only happens between chunks"),
+        }
+        Ok(())
+    }
+}
+
+// chunk-size [ chunk-ext ], where chunk-ext = *( BWS ";" BWS ext-name [ BWS "=" BWS ext-val ] )
+fn parse_chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    let invalid = || ParseError::MalformedRequest(format!("invalid chunk size {:?}", String::from_utf8_lossy(line)));
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let size = match line.split_once(';') {
+        Some((size, _extensions)) => size,
+        None => line,
+    };
+    let size = size.trim_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    usize::from_str_radix(size, 16).map_err(|_| invalid())
+}
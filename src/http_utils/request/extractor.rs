@@ -1,40 +1,100 @@
 use crate::http_utils::types::{ParsedRequest, UniversalBody};
-use std::collections::HashMap;
 use crate::http_utils::status::ParseError;
-use crate::http_utils::request::request_logic::query_to_map;
+use crate::http_utils::request::handler::Request;
+use crate::http_utils::query::QueryMap;
+use crate::http_utils::request::request_logic::path_segments;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
 
-pub fn extract_path_from_buffer(buffer: &[u8]) -> Option<String> {
-    let request_line = buffer
-        .split(|&b| b == b'\r' || b == b'\n')
-        .next()?; // Get the first line (request line)
-
-    let request_line_str = std::str::from_utf8(request_line).ok()?;
-    let mut parts = request_line_str.split_whitespace();
-
-    let _method = parts.next()?; // Skip method
-    let path = parts.next()?;    // This is the path
-    Some(path.to_string())
-}
-
-pub fn extract_request_parts(parsed_request: ParsedRequest) -> Result<(UniversalBody, String, String, HashMap<String, String>, HashMap<String, String>), ParseError>{
-    let (request_path, request_method, body, headers) = match &parsed_request {
-        ParsedRequest::Api(api_req) => (api_req.path.as_str(), api_req.method.as_str(), api_req.body.clone(), api_req.headers.clone()),
-        ParsedRequest::HTTP(http_req) => (http_req.path.as_str(), http_req.method.as_str(), http_req.body.clone(), http_req.headers.clone()),
+pub fn extract_request_parts(parsed_request: ParsedRequest, remote_addr: SocketAddr) -> Result<Request, ParseError>{
+    let (request_path, request_method, version, body, headers, trailers) = match parsed_request {
+        ParsedRequest::Api(api_req) => (api_req.path, api_req.method, api_req.version, api_req.body, api_req.headers, api_req.trailers),
+        ParsedRequest::Http(http_req) => (http_req.path, http_req.method, http_req.version, http_req.body, http_req.headers, http_req.trailers),
     };
+    let request_path = request_path.as_str();
 
-    println!("Body: {:?}", body);
+    log_debug!("Body: {:?}", body);
 
     let (path, query) = match request_path.find('?') {
         Some(i) => {(&request_path[..i], &request_path[i + 1..])}
         None => (request_path, ""),
     };
 
-    println!("Path: {}", path);
-    println!("Query: {}", query);
+    log_debug!("Path: {}", path);
+    log_debug!("Query: {}", query);
+
+    // The path is kept as sent and decoded a segment at a time when it is routed.
+    // One that doesn't decode at all is refused here, before any middleware runs.
+    path_segments(path)?;
+    let query_map = QueryMap::parse(query)?;
+
+    log_debug!("Query Map: {:#?}", query_map);
+
+    let mut request = Request::new(&request_method, path, remote_addr);
+    request.version = version;
+    request.headers = headers;
+    request.trailers = trailers;
+    request.query_map = query_map;
+    request.body = body;
+    Ok(request)
+}
+
+// Something a handler can pull out of a request with `Request::extract`. A
+// failure is the error the request is answered with, so `?` is all it takes.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, ParseError>;
+}
+
+// An `application/json` body read into a `T`. Invalid JSON is refused with a 400
+// before the handler runs, JSON of the wrong shape gets a 422.
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Self, ParseError> {
+        let UniversalBody::Json(value) = &request.body else {
+            return Err(ParseError::UnsupportedMediaType("expected an application/json body".to_string()));
+        };
+        T::deserialize(value)
+            .map(Json)
+            .map_err(|e| ParseError::UnprocessableContent(e.to_string()))
+    }
+}
+
+// The query string read into a `T`, a 400 when it doesn't fit
+#[derive(Debug)]
+pub struct Query<T>(pub T);
 
-    let query_map = query_to_map(query);
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request) -> Result<Self, ParseError> {
+        request.query_map.deserialize().map(Query)
+    }
+}
+
+// An `application/x-www-form-urlencoded` body read into a `T`, a 422 when it doesn't fit
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(request: &Request) -> Result<Self, ParseError> {
+        let UniversalBody::Form(fields) = &request.body else {
+            return Err(ParseError::UnsupportedMediaType("expected an application/x-www-form-urlencoded body".to_string()));
+        };
+        serde_urlencoded::from_str(fields.as_encoded())
+            .map(Form)
+            .map_err(|e| ParseError::UnprocessableContent(e.to_string()))
+    }
+}
 
-    println!("Query Map: {:#?}", query_map);
+// The `:name` segments of the route read into a `T`, a 400 when they don't fit.
+// They go through the same parsing as a query, so `id: u32` works.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
 
-    Ok((body, path.to_string(), request_method.to_string(), query_map, headers))
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(request: &Request) -> Result<Self, ParseError> {
+        let invalid = |e: &dyn std::fmt::Display| ParseError::MalformedRequest(format!("invalid path parameters: {}", e));
+        let encoded = serde_urlencoded::to_string(&request.params).map_err(|e| invalid(&e))?;
+        serde_urlencoded::from_str(&encoded).map(Path).map_err(|e| invalid(&e))
+    }
 }
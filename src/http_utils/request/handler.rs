@@ -0,0 +1,152 @@
+use crate::http_utils::headers::HeaderMap;
+use crate::http_utils::media_type::MediaType;
+use crate::http_utils::parser::RequestHead;
+use crate::http_utils::query::QueryMap;
+use crate::http_utils::request::extractor::FromRequest;
+use crate::http_utils::request::router::PathParams;
+use crate::http_utils::status::ParseError;
+use crate::http_utils::types::{Response, UniversalBody};
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+pub type AppState = Arc<dyn Any + Send + Sync>;
+pub type HandlerResult = Result<Response, ParseError>;
+
+// Everything a handler gets to know about the request it is answering
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    // As sent, still percent-encoded. `params` hold the decoded segments.
+    pub path: String,
+    pub version: String,
+    pub headers: HeaderMap,
+    // Fields sent after a chunked body, never mixed into `headers`
+    pub trailers: HeaderMap,
+    pub params: PathParams,
+    pub query_map: QueryMap,
+    pub body: UniversalBody,
+    pub remote_addr: SocketAddr,
+    state: Option<AppState>,
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str, remote_addr: SocketAddr) -> Self {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+            params: PathParams::new(),
+            query_map: QueryMap::new(),
+            body: UniversalBody::Text(String::new()),
+            remote_addr,
+            state: None,
+        }
+    }
+
+    pub fn with_state(mut self, state: Option<AppState>) -> Self {
+        self.state = state;
+        self
+    }
+
+    // The query string as a `T`, e.g. a struct deriving `Deserialize`
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, ParseError> {
+        self.query_map.deserialize()
+    }
+
+    // `Json<T>`, `Query<T>`, `Form<T>` or `Path<T>`, or any other `FromRequest`
+    pub fn extract<E: FromRequest>(&self) -> Result<E, ParseError> {
+        E::from_request(self)
+    }
+
+    // The state registered with `ServerBuilder::state`, if it is a `T`
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state.clone()?.downcast::<T>().ok()
+    }
+}
+
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, request: Request) -> BoxFuture<HandlerResult>;
+
+    // Passes `Middleware::check_head` on to middleware wrapped into a handler
+    fn check_head(&self, _head: &RequestHead) -> Option<Response> {
+        None
+    }
+}
+
+// Any `async fn(Request) -> HandlerResult`, or a closure returning such a future
+impl<F, Fut> Handler for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    fn call(&self, request: Request) -> BoxFuture<HandlerResult> {
+        Box::pin(self(request))
+    }
+}
+
+struct SyncHandler<F>(F);
+
+impl<F> Handler for SyncHandler<F>
+where
+    F: Fn(Request) -> HandlerResult + Send + Sync + 'static,
+{
+    fn call(&self, request: Request) -> BoxFuture<HandlerResult> {
+        let result = (self.0)(request);
+        Box::pin(async move { result })
+    }
+}
+
+// Wraps a plain function so the blocking handlers in `routes` and `api` can be registered as is
+pub fn sync<F>(handler: F) -> impl Handler
+where
+    F: Fn(Request) -> HandlerResult + Send + Sync + 'static,
+{
+    SyncHandler(handler)
+}
+
+struct Accepts<H> {
+    types: Vec<MediaType>,
+    handler: H,
+}
+
+impl<H: Handler> Handler for Accepts<H> {
+    fn call(&self, request: Request) -> BoxFuture<HandlerResult> {
+        let content_type = request.headers.get("Content-Type");
+        let has_body = !matches!(&request.body, UniversalBody::Text(text) if text.is_empty())
+            && !matches!(&request.body, UniversalBody::Binary(bytes) if bytes.is_empty());
+        let accepted = match content_type.map(MediaType::parse) {
+            Some(Ok(media_type)) => self.types.iter().any(|pattern| media_type.matches(pattern)),
+            Some(Err(_)) => false,
+            // Nothing to check when nothing was sent
+            None => !has_body,
+        };
+        if !accepted {
+            let expected = self.types.iter().map(MediaType::essence).collect::<Vec<_>>().join(", ");
+            let error = ParseError::UnsupportedMediaType(format!("expected {}, got {}", expected, content_type.unwrap_or("no Content-Type")));
+            return Box::pin(async move { Err(error) });
+        }
+        self.handler.call(request)
+    }
+
+    fn check_head(&self, head: &RequestHead) -> Option<Response> {
+        self.handler.check_head(head)
+    }
+}
+
+// Refuses with a 415 a request body whose `Content-Type` matches none of `types`,
+// e.g. `accepts(&["application/json", "application/*+json"], handler)`.
+// Panics on a type that doesn't parse, as that is a mistake in the routes.
+pub fn accepts(types: &[&str], handler: impl Handler) -> impl Handler {
+    let types = types
+        .iter()
+        .map(|media_type| MediaType::parse(media_type).unwrap_or_else(|_| panic!("invalid media type {:?}", media_type)))
+        .collect();
+    Accepts { types, handler }
+}
@@ -1,4 +1,7 @@
 pub mod reader;
 pub mod request_logic;
 pub mod router;
-pub mod extractor;
\ No newline at end of file
+pub mod extractor;
+pub mod chunked;
+pub mod multipart;
+pub mod handler;
\ No newline at end of file
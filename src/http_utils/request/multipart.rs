@@ -0,0 +1,451 @@
+use crate::http_utils::headers::HeaderMap;
+use crate::http_utils::media_type::MediaType;
+use crate::http_utils::status::ParseError;
+use crate::server::ServerOptions;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+// Largest block of headers we are willing to buffer for one part
+const MAX_PART_HEADER_BYTES: usize = 8192;
+
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    // Largest single part, fields and files alike, answered with 413 when over
+    pub max_part_bytes: usize,
+    // Largest content of all parts together, answered with 413 when over
+    pub max_total_bytes: usize,
+    // File parts growing past this many bytes are moved from memory to `upload_dir`
+    pub memory_bytes: usize,
+    pub upload_dir: PathBuf,
+}
+
+impl MultipartLimits {
+    pub fn from_options(options: &ServerOptions) -> Self {
+        MultipartLimits {
+            max_part_bytes: options.max_part_bytes,
+            max_total_bytes: options.max_body_bytes,
+            memory_bytes: options.multipart_memory_bytes,
+            upload_dir: options.upload_dir.clone(),
+        }
+    }
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits::from_options(&ServerOptions::default())
+    }
+}
+
+// An uploaded file written to disk. It is deleted once the last part holding it
+// is dropped, unless it was moved somewhere else with `persist`.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    persisted: AtomicBool,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> io::Result<(TempFile, File)> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!("upload-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+            let path = dir.join(name);
+            // Never reuses a file that is already there
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            // Uploads may be private, only the server gets to read them
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => return Ok((TempFile { path, persisted: AtomicBool::new(false) }, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Moves the file to `to`, where it is kept
+    pub fn persist(&self, to: impl AsRef<Path>) -> io::Result<()> {
+        fs::rename(&self.path, to)?;
+        self.persisted.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted.load(Ordering::Relaxed) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(Arc<TempFile>),
+}
+
+// One field or file of a `multipart/form-data` body (RFC 7578)
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub name: String,
+    // Only set for file parts
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+    pub data: PartData,
+    len: usize,
+}
+
+impl Part {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    // The content of a part kept in memory, if it is text
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            PartData::Memory(bytes) => std::str::from_utf8(bytes).ok(),
+            PartData::File(_) => None,
+        }
+    }
+
+    // The whole content, read back from disk for a part that was written there
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File(file) => fs::read(file.path()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Multipart {
+    parts: Vec<Part>,
+}
+
+impl Multipart {
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+
+    // Text of the first field named `name` that isn't a file
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.parts.iter().find(|part| part.name == name && !part.is_file()).and_then(Part::text)
+    }
+
+    // First file part named `name`
+    pub fn file(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name && part.is_file())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter().filter(|part| part.is_file())
+    }
+}
+
+// The `boundary` parameter of a `multipart/form-data` content type, `None` for
+// any other type. Multipart without a usable boundary can't be read at all.
+pub fn multipart_boundary(content_type: &str) -> Result<Option<String>, ParseError> {
+    let Ok(media_type) = MediaType::parse(content_type) else {
+        return Ok(None);
+    };
+    if media_type.essence() != "multipart/form-data" {
+        return Ok(None);
+    }
+    media_type
+        .param("boundary")
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+        .map(|boundary| Some(boundary.to_string()))
+        .ok_or_else(|| ParseError::MalformedRequest("multipart/form-data without a valid boundary".to_string()))
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Preamble,
+    // Right after a delimiter, either `--` for the last one or the end of its line
+    AfterDelimiter,
+    Headers,
+    Body,
+    Epilogue,
+}
+
+// A part whose headers are in and whose content is still arriving
+struct OpenPart {
+    part: Part,
+    file: Option<File>,
+}
+
+// Incremental parser for `multipart/form-data` bodies (RFC 2046 section 5.1.1).
+// Bytes can be fed in whatever pieces they arrive from the socket, and only
+// about one delimiter's worth of them is held back at any time.
+pub struct MultipartParser {
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    state: State,
+    buffer: Vec<u8>,
+    open: Option<OpenPart>,
+    parts: Vec<Part>,
+    total: usize,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: MultipartLimits) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            limits,
+            state: State::Preamble,
+            // The first delimiter may open the body, without a line break before it
+            buffer: b"\r\n".to_vec(),
+            open: None,
+            parts: Vec::new(),
+            total: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Epilogue
+    }
+
+    pub fn feed(&mut self, input: &[u8]) -> Result<(), ParseError> {
+        if self.is_done() {
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(input);
+        loop {
+            let progressed = match self.state {
+                State::Preamble => self.skip_preamble(),
+                State::AfterDelimiter => self.after_delimiter()?,
+                State::Headers => self.read_headers()?,
+                State::Body => self.read_body()?,
+                State::Epilogue => {
+                    self.buffer.clear();
+                    false
+                }
+            };
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    // Every part, once the closing delimiter was seen
+    pub fn finish(self) -> Result<Multipart, ParseError> {
+        if !self.is_done() {
+            return Err(ParseError::MalformedRequest("multipart body ended before its closing boundary".to_string()));
+        }
+        Ok(Multipart { parts: self.parts })
+    }
+
+    fn find_delimiter(&self) -> Option<usize> {
+        self.buffer.windows(self.delimiter.len()).position(|window| window == self.delimiter)
+    }
+
+    // How much of the buffer can't be the start of a delimiter
+    fn safe_len(&self) -> usize {
+        self.buffer.len().saturating_sub(self.delimiter.len() - 1)
+    }
+
+    fn skip_preamble(&mut self) -> bool {
+        match self.find_delimiter() {
+            Some(i) => {
+                self.buffer.drain(..i + self.delimiter.len());
+                self.state = State::AfterDelimiter;
+                true
+            }
+            None => {
+                let safe = self.safe_len();
+                self.buffer.drain(..safe);
+                false
+            }
+        }
+    }
+
+    fn after_delimiter(&mut self) -> Result<bool, ParseError> {
+        if self.buffer.starts_with(b"--") {
+            self.buffer.clear();
+            self.state = State::Epilogue;
+            return Ok(true);
+        }
+        // Anything but whitespace before the line ends means the boundary was only a prefix
+        let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") else {
+            if self.buffer.len() > MAX_PART_HEADER_BYTES || !self.buffer.iter().all(|b| matches!(b, b' ' | b'\t' | b'\r' | b'-')) {
+                return Err(ParseError::MalformedRequest("garbage after a multipart boundary".to_string()));
+            }
+            return Ok(false);
+        };
+        if !self.buffer[..end].iter().all(|b| matches!(b, b' ' | b'\t')) {
+            return Err(ParseError::MalformedRequest("garbage after a multipart boundary".to_string()));
+        }
+        self.buffer.drain(..end + 2);
+        self.state = State::Headers;
+        Ok(true)
+    }
+
+    fn read_headers(&mut self) -> Result<bool, ParseError> {
+        // A part may have no headers at all, then the blank line comes right away
+        let end = if self.buffer.starts_with(b"\r\n") {
+            Some(0)
+        } else {
+            self.buffer.windows(4).position(|window| window == b"\r\n\r\n").map(|i| i + 2)
+        };
+        let Some(end) = end else {
+            if self.buffer.len() > MAX_PART_HEADER_BYTES {
+                return Err(ParseError::InvalidHeader(format!("multipart part headers over {} bytes", MAX_PART_HEADER_BYTES)));
+            }
+            return Ok(false);
+        };
+
+        let head = std::str::from_utf8(&self.buffer[..end])
+            .map_err(|_| ParseError::InvalidHeader("multipart part headers are not UTF-8".to_string()))?;
+        let mut headers = HeaderMap::new();
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or_else(|| ParseError::InvalidHeader(format!("no colon in {:?}", line)))?;
+            headers.append(name.trim(), value.trim());
+        }
+        self.buffer.drain(..end + 2);
+
+        let disposition = headers.get("Content-Disposition").unwrap_or_default();
+        let params = disposition_params(disposition);
+        let param = |key: &str| params.iter().find(|(name, _)| name.eq_ignore_ascii_case(key)).map(|(_, value)| value.clone());
+        let Some(name) = param("name") else {
+            return Err(ParseError::MalformedRequest("multipart part without a name".to_string()));
+        };
+        let part = Part {
+            name,
+            filename: param("filename"),
+            content_type: headers.get("Content-Type").map(str::to_string),
+            headers,
+            data: PartData::Memory(Vec::new()),
+            len: 0,
+        };
+        self.open = Some(OpenPart { part, file: None });
+        self.state = State::Body;
+        Ok(true)
+    }
+
+    fn read_body(&mut self) -> Result<bool, ParseError> {
+        match self.find_delimiter() {
+            Some(i) => {
+                let content: Vec<u8> = self.buffer.drain(..i + self.delimiter.len()).take(i).collect();
+                self.write(&content)?;
+                let open = self.open.take().expect("a part is open while its body is read");
+                if let Some(mut file) = open.file {
+                    blocking(|| file.flush()).map_err(storage_error)?;
+                }
+                self.parts.push(open.part);
+                self.state = State::AfterDelimiter;
+                Ok(true)
+            }
+            None => {
+                let safe = self.safe_len();
+                let content: Vec<u8> = self.buffer.drain(..safe).collect();
+                self.write(&content)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn write(&mut self, content: &[u8]) -> Result<(), ParseError> {
+        if content.is_empty() {
+            return Ok(());
+        }
+        let open = self.open.as_mut().expect("a part is open while its body is read");
+        if open.part.len + content.len() > self.limits.max_part_bytes {
+            log_debug!("Multipart part {:?} is over {} bytes", open.part.name, self.limits.max_part_bytes);
+            return Err(ParseError::BodyTooLarge(self.limits.max_part_bytes));
+        }
+        if self.total + content.len() > self.limits.max_total_bytes {
+            return Err(ParseError::BodyTooLarge(self.limits.max_total_bytes));
+        }
+        open.part.len += content.len();
+        self.total += content.len();
+
+        if let PartData::Memory(bytes) = &mut open.part.data {
+            // Only files go to disk, a field that big is refused by the part limit
+            if open.part.filename.is_none() || bytes.len() + content.len() <= self.limits.memory_bytes {
+                bytes.extend_from_slice(content);
+                return Ok(());
+            }
+            let (temp_file, file) = blocking(|| {
+                let (temp_file, mut file) = TempFile::create(&self.limits.upload_dir)?;
+                file.write_all(bytes)?;
+                Ok((temp_file, file))
+            })
+            .map_err(storage_error)?;
+            log_debug!("Writing upload {:?} to {}", open.part.filename, temp_file.path().display());
+            open.part.data = PartData::File(Arc::new(temp_file));
+            open.file = Some(file);
+        }
+        let file = open.file.as_mut().expect("file parts on disk keep their file open");
+        blocking(|| file.write_all(content)).map_err(storage_error)
+    }
+}
+
+// Failing to write an upload is our problem, not a sign the client went away
+fn storage_error(e: io::Error) -> ParseError {
+    log_error!("Failed to write an upload: {}", e);
+    ParseError::Storage(e.to_string())
+}
+
+// Runs disk I/O without holding up the other connections on this worker. A
+// single threaded runtime has no other worker to hand them to, there it just blocks.
+fn blocking<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+// `form-data; name="field"; filename="a;b.txt"` -> [("name", "field"), ("filename", "a;b.txt")]
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = value.split_once(';').map(|(_, rest)| rest).unwrap_or_default();
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().to_string();
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                let next = quoted[end..].split_once(';').map(|(_, next)| next).unwrap_or_default();
+                (value, next)
+            }
+            None => match after.split_once(';') {
+                Some((value, next)) => (value.trim().to_string(), next),
+                None => (after.trim().to_string(), ""),
+            },
+        };
+        params.push((name, value));
+        rest = next;
+    }
+    params
+}
@@ -1,43 +1,46 @@
 use crate::http_utils::status::ParseError;
-use crate::http_utils::parser;
+use crate::http_utils::parser::HeadParser;
+use crate::http_utils::types::RawRequest;
+use crate::http_utils::request::chunked::ChunkedDecoder;
+use crate::http_utils::request::multipart::{multipart_boundary, MultipartLimits, MultipartParser};
 use tokio::net::TcpStream;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::server::ServerOptions;
 use tokio::time::timeout;
-use tokio::time::Duration;
 
-
-async fn read_header<'a>(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &'a mut Vec<u8>) -> Result<&'a mut Vec<u8>, ParseError> {
+// `dynamo_buffer` may already hold part or all of the head, left over from the previous request
+async fn read_header(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &mut Vec<u8>, parser: &mut HeadParser) -> Result<usize, ParseError> {
     loop {
+        if let Some(head) = parser.parse(dynamo_buffer)? {
+            return Ok(head.len);
+        }
+
         match stream.read(pre_buffer).await {
             Ok(0) => {
-                eprintln!("Connection closed before complete headers");
+                log_debug!("Connection closed before complete headers");
                 return Err(ParseError::ConnectionAborted)
             }
             Ok(n) => {
                 dynamo_buffer.extend_from_slice(&pre_buffer[..n]); // Only use bytes read
-                if dynamo_buffer.windows(4).any(|window| window == b"\r\n\r\n") {
-                    break;
-                }
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                eprintln!("Connection timed out");
-                return Err(ParseError::ConnectionAborted);
+                log_debug!("Connection timed out");
+                return Err(ParseError::Timeout);
             }
 
             Err(e) => {
-                eprintln!("Failed to read from stream: {}", e);
-                return Err(ParseError::MalformedRequest); 
+                log_debug!("Failed to read from stream: {}", e);
+                return Err(ParseError::Io(e.to_string()));
             }
         }
     }
-    Ok(dynamo_buffer)
 }
 
 async fn read_body<'a>(content_length: usize, stream: &mut TcpStream, full_body: &'a mut Vec<u8>) -> Result<&'a mut Vec<u8>, ParseError> {
-    if content_length == 0 {
+    if content_length <= full_body.len() {
         return Ok(full_body);
     }
-    
+
     let mut body_buffer = vec![0; content_length - full_body.len()];
     let read_result = stream.read_exact(&mut body_buffer);
 
@@ -46,59 +49,221 @@ async fn read_body<'a>(content_length: usize, stream: &mut TcpStream, full_body:
             full_body.extend_from_slice(&body_buffer);
             Ok(full_body)
         },
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            log_debug!("Connection closed before complete body");
+            Err(ParseError::ConnectionAborted)
+        }
         Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-            eprintln!("Connection timed out");
-            return Err(ParseError::ConnectionAborted);
+            log_debug!("Connection timed out");
+            Err(ParseError::Timeout)
         }
         Err(e) => {
-            eprintln!("Failed to read body: {}", e);
-            return Err(ParseError::MalformedRequest);
+            log_debug!("Failed to read body: {}", e);
+            Err(ParseError::Io(e.to_string()))
         }
     }
 }
 
-pub async fn full_read_request(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &mut Vec<u8>) -> Result<Vec<u8>, ParseError> {
+// Like `read_body`, but the body goes to `multipart` piece by piece as it arrives
+// instead of being kept whole. Returns the bytes read past the end of the body.
+async fn read_multipart_body(content_length: usize, stream: &mut TcpStream, pre_buffer: &mut [u8], already_read: &[u8], multipart: &mut MultipartParser) -> Result<Vec<u8>, ParseError> {
+    let in_buffer = content_length.min(already_read.len());
+    multipart.feed(&already_read[..in_buffer])?;
+    let mut remaining = content_length - in_buffer;
+    while remaining > 0 {
+        let limit = remaining.min(pre_buffer.len());
+        match stream.read(&mut pre_buffer[..limit]).await {
+            Ok(0) => {
+                log_debug!("Connection closed before complete body");
+                return Err(ParseError::ConnectionAborted);
+            }
+            Ok(n) => {
+                multipart.feed(&pre_buffer[..n])?;
+                remaining -= n;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                log_debug!("Connection timed out");
+                return Err(ParseError::Timeout);
+            }
+            Err(e) => {
+                log_debug!("Failed to read body: {}", e);
+                return Err(ParseError::Io(e.to_string()));
+            }
+        }
+    }
+    Ok(already_read[in_buffer..].to_vec())
+}
 
-    println!("Reading header...");
-    match timeout(Duration::from_secs(10), read_header(stream, pre_buffer, dynamo_buffer)).await {
-        Ok(_) => {
-            println!("Header read");
+// Returns the bytes read past the end of the body, which belong to the next request.
+// With `multipart` the decoded data is handed to it as it comes instead of kept.
+async fn read_chunked_body(stream: &mut TcpStream, pre_buffer: &mut [u8], decoder: &mut ChunkedDecoder, already_read: &[u8], max_body_bytes: usize, mut multipart: Option<&mut MultipartParser>) -> Result<Vec<u8>, ParseError> {
+    let consumed = decoder.feed(already_read)?;
+    let mut leftover = already_read[consumed..].to_vec();
+    loop {
+        if let Some(multipart) = multipart.as_deref_mut() {
+            multipart.feed(&decoder.take_body())?;
+        }
+        if decoder.body_len() > max_body_bytes {
+            log_debug!("Chunked body is over {} bytes", max_body_bytes);
+            return Err(ParseError::BodyTooLarge(max_body_bytes));
+        }
+        if decoder.is_done() {
+            return Ok(leftover);
+        }
+        match stream.read(pre_buffer).await {
+            Ok(0) => {
+                log_debug!("Connection closed before last chunk");
+                return Err(ParseError::ConnectionAborted);
+            }
+            Ok(n) => {
+                let consumed = decoder.feed(&pre_buffer[..n])?;
+                leftover = pre_buffer[consumed..n].to_vec();
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                log_debug!("Connection timed out");
+                return Err(ParseError::Timeout);
+            }
+            Err(e) => {
+                log_debug!("Failed to read chunk: {}", e);
+                return Err(ParseError::Io(e.to_string()));
+            }
+        }
+    }
+}
+
+async fn send_continue(stream: &mut TcpStream) -> Result<(), ParseError> {
+    log_debug!("Sending 100 Continue");
+    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.map_err(|e| {
+        log_debug!("Failed to send 100 Continue: {}", e);
+        ParseError::Io(e.to_string())
+    })
+}
+
+// Reads up to the end of the request head and parses it. `dynamo_buffer` lives as
+// long as the connection: it may start with bytes already received, and is left
+// holding whatever was read past the head.
+pub async fn read_request_head(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &mut Vec<u8>, options: &ServerOptions) -> Result<RawRequest, ParseError> {
+    let mut parser = HeadParser::new().with_limits(options.max_request_line_bytes, options.max_header_bytes, options.max_header_count);
+
+    log_debug!("Reading header...");
+    let head_length = match timeout(options.header_timeout, read_header(stream, pre_buffer, dynamo_buffer, &mut parser)).await {
+        Ok(Ok(head_length)) => {
+            log_debug!("Header read");
+            head_length
         },
+        Ok(Err(e)) => {
+            log_debug!("Error reading header: {:?}", e);
+            return Err(e);
+        }
         Err(e) => {
-            eprintln!("Error reading header: {:?}", e);
-            return Err(ParseError::ConnectionAborted);
+            log_debug!("Error reading header: {:?}", e);
+            return Err(ParseError::Timeout);
+        }
+    };
+
+    let rest = dynamo_buffer.split_off(head_length);
+    let head = std::mem::replace(dynamo_buffer, rest);
+    Ok(RawRequest::new(head, parser))
+}
+
+// Reads the body of `request`. Whatever is read past it, e.g. the start of the next
+// pipelined request, is left in `dynamo_buffer`. A client waiting on
+// `Expect: 100-continue` is told to go on once the body is known to fit, and only
+// if it hasn't started sending it already.
+pub async fn read_request_body(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &mut Vec<u8>, request: &mut RawRequest, options: &ServerOptions) -> Result<(), ParseError> {
+    let head = request.head();
+    let expects_continue = head.expects_continue();
+    let mut multipart = multipart_boundary(head.header("Content-Type").unwrap_or_default())?
+        .map(|boundary| MultipartParser::new(&boundary, MultipartLimits::from_options(options)));
+
+    if head.is_chunked() {
+        if expects_continue && dynamo_buffer.is_empty() {
+            send_continue(stream).await?;
         }
+        let already_read_body = std::mem::take(dynamo_buffer);
+        // The trailer section counts against the limits the head was held to
+        let mut decoder = ChunkedDecoder::new().with_limits(
+            options.max_header_bytes.saturating_sub(request.head.len()),
+            options.max_header_count.saturating_sub(head.headers.len()),
+        );
+        match timeout(options.body_timeout, read_chunked_body(stream, pre_buffer, &mut decoder, &already_read_body, options.max_body_bytes, multipart.as_mut())).await {
+            Ok(Ok(leftover)) => *dynamo_buffer = leftover,
+            Ok(Err(e)) => {
+                log_debug!("Error reading chunked body: {:?}", e);
+                return Err(e);
+            }
+            Err(e) => {
+                log_debug!("Error reading chunked body: {:?}", e);
+                return Err(ParseError::Timeout);
+            }
+        }
+
+        log_debug!("Chunked body: {} bytes", decoder.body_len());
+        (request.body, request.trailers) = decoder.into_parts();
+        request.multipart = multipart.map(MultipartParser::finish).transpose()?;
+        return Ok(());
     }
-    
-    let header_end = dynamo_buffer.windows(4).position(|window| window == b"\r\n\r\n");
-    let content_length = parser::get_content_length(&dynamo_buffer);
-    let body_start = header_end.unwrap() + 4;
-    let already_read_body =  &dynamo_buffer[body_start..];
-    let mut full_body = already_read_body.to_vec();
-
-    let content_length = match content_length {
+
+    let content_length = match head.content_length() {
         Ok(content_length) => {
             content_length
         }
         Err(e) => {
-            eprintln!("Error getting content length: {:?}", e);
-            let error = e;
-            return Err(error);
+            log_debug!("Error getting content length: {:?}", e);
+            return Err(e);
         }
     };
+    // Refused before anything is allocated for it, or the client is told to send it
+    if content_length > options.max_body_bytes {
+        log_debug!("Content-Length {} is over {} bytes", content_length, options.max_body_bytes);
+        return Err(ParseError::BodyTooLarge(options.max_body_bytes));
+    }
+    if expects_continue && content_length > 0 && dynamo_buffer.is_empty() {
+        send_continue(stream).await?;
+    }
 
-    match timeout(Duration::from_secs(10), read_body(content_length, stream, &mut full_body)).await {
-        Ok(_) => {},
+    if let Some(mut multipart) = multipart {
+        let already_read_body = std::mem::take(dynamo_buffer);
+        match timeout(options.body_timeout, read_multipart_body(content_length, stream, pre_buffer, &already_read_body, &mut multipart)).await {
+            Ok(Ok(leftover)) => *dynamo_buffer = leftover,
+            Ok(Err(e)) => {
+                log_debug!("Error reading multipart body: {:?}", e);
+                return Err(e);
+            }
+            Err(e) => {
+                log_debug!("Error reading multipart body: {:?}", e);
+                return Err(ParseError::Timeout);
+            }
+        }
+        let multipart = multipart.finish()?;
+        log_debug!("Multipart body: {} parts", multipart.parts().len());
+        request.multipart = Some(multipart);
+        return Ok(());
+    }
+
+    let mut full_body = std::mem::take(dynamo_buffer);
+    match timeout(options.body_timeout, read_body(content_length, stream, &mut full_body)).await {
+        Ok(Ok(_)) => {},
+        Ok(Err(e)) => {
+            log_debug!("Error reading body: {:?}", e);
+            return Err(e);
+        }
         Err(e) => {
-            eprintln!("Error reading body: {:?}", e);
-            return Err(ParseError::ConnectionAborted);
+            log_debug!("Error reading body: {:?}", e);
+            return Err(ParseError::Timeout);
         }
     }
 
-    let mut full_request = dynamo_buffer[..body_start].to_vec();
-    full_request.extend_from_slice(&full_body);
+    // Anything past the body is the next request
+    *dynamo_buffer = full_body.split_off(content_length);
+    request.body = full_body;
+    log_debug!("Body: {} bytes", request.body.len());
+    Ok(())
+}
 
-    println!("FULL Request: {}", String::from_utf8_lossy(&full_request[..]));
-    println!("================================");
-    Ok(full_request)
+// Reads one whole request, head and body
+pub async fn full_read_request(stream: &mut TcpStream, pre_buffer: &mut [u8], dynamo_buffer: &mut Vec<u8>, options: &ServerOptions) -> Result<RawRequest, ParseError> {
+    let mut request = read_request_head(stream, pre_buffer, dynamo_buffer, options).await?;
+    read_request_body(stream, pre_buffer, dynamo_buffer, &mut request, options).await?;
+    Ok(request)
 }
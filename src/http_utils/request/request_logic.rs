@@ -1,40 +1,84 @@
+use crate::http_utils::headers::HeaderMap;
+use crate::http_utils::parser::RequestHead;
+use crate::http_utils::query::percent_decode;
+use crate::http_utils::request::router::{RouteMatch, Router};
+use crate::http_utils::response::{ApiError, IntoResponse};
 use crate::http_utils::status::ParseError;
+use crate::http_utils::types::Response;
 use crate::routes::web;
-use std::collections::HashMap;
 
-use crate::http_utils::request::extractor::extract_path_from_buffer;
 
-pub fn sanitize_path(path: &str) -> Option<&str> {
-    if path.contains("..") || path.contains('\0') || path.contains("/.") {
-        None
+// A request path split into its segments, each percent-decoded on its own so an
+// encoded `/` stays part of its segment. `None` if a decoded segment could reach
+// outside the root or a hidden file.
+pub fn path_segments(path: &str) -> Result<Option<Vec<String>>, ParseError> {
+    let mut decoded = Vec::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let segment = percent_decode(segment, false)?;
+        if segment.contains("..") || segment.contains('\0') || segment.starts_with('.') || segment.contains("/.") {
+            return Ok(None);
+        }
+        decoded.push(segment);
+    }
+    Ok(Some(decoded))
+}
+
+pub fn is_api_request(target: &str) -> bool {
+    target.starts_with("/api/")
+}
+
+// HTTP/1.1 connections stay open unless the client says `Connection: close`,
+// HTTP/1.0 ones close unless it says `Connection: keep-alive`
+pub fn wants_keep_alive(version: &str, headers: &HeaderMap) -> bool {
+    if version == "HTTP/1.0" {
+        headers.has_token("Connection", "keep-alive")
     } else {
-        Some(path)
+        !headers.has_token("Connection", "close")
     }
 }
 
-pub fn is_api_request(buffer: &[u8]) -> bool {
-    let res = match extract_path_from_buffer(buffer) {
-        Some(path) => path.starts_with("/api/"),
-        None => false,
+// For a request sent with `Expect`, checked once its head is in: the response to
+// send instead of reading the body, if the body would be refused anyway. Middleware
+// only runs once the whole request is read, it gets a say here through `check_head`.
+pub fn reject_expectation(head: &RequestHead, router: &Router) -> Option<Response> {
+    let expect = head.header("Expect")?;
+    let is_api = is_api_request(head.target);
+    if !expect.eq_ignore_ascii_case("100-continue") {
+        return Some(web::handle_417(is_api));
+    }
+    if !head.expects_continue() {
+        return None;
+    }
+
+    // A target that doesn't decode is refused once the body is read
+    let path = head.target.split('?').next().unwrap_or_default();
+    let Some(parts) = path_segments(path).ok()? else {
+        return Some(web::handle_403(is_api));
     };
-    res
+    if let Some(response) = router.check_head(head, &parts) {
+        return Some(response);
+    }
+    match router.find(head.method, &parts) {
+        RouteMatch::Found(..) => None,
+        RouteMatch::MethodNotAllowed(_) if head.method == "OPTIONS" => None,
+        RouteMatch::MethodNotAllowed(allowed) => Some(web::handle_405(&allowed.join(", "), is_api)),
+        RouteMatch::NotFound => Some(web::handle_404(is_api)),
+    }
 }
 
-pub fn query_to_map(query: &str) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    for pair in query.split('&') {
-        let (key, value) = match pair.split_once('=') {
-            None => (pair, ""),
-            Some((key, value)) => (key, value),
-        };
-        map.insert(key.to_string(), value.to_string());
-    }
-    map
+// Whether a request head that may not have parsed is for the API, to answer its
+// errors in JSON. Only the target is looked at.
+pub fn targets_api(buffer: &[u8]) -> bool {
+    let start = buffer.iter().position(|&b| b != b'\r' && b != b'\n').unwrap_or(buffer.len());
+    let mut parts = buffer[start..].split(|&b| b == b' ');
+    parts.nth(1).is_some_and(|target| target.starts_with(b"/api/"))
 }
 
-pub fn error_handler(error: ParseError) -> Vec<u8> {
-    match error {
-        ParseError::MalformedRequest => web::handle_400(),
-        ParseError::ConnectionAborted => web::handle_408(),
+// API clients get the error as JSON, everyone else an HTML page
+pub fn error_handler(error: ParseError, is_api: bool) -> Response {
+    if is_api {
+        ApiError(error).into_response()
+    } else {
+        error.into_response()
     }
 }
@@ -1,35 +1,291 @@
-use crate::http_utils::types::{UniversalBody};
+use crate::http_utils::parser::METHODS;
+use crate::http_utils::request::handler::{sync, Handler, HandlerResult, Request};
+use crate::http_utils::middleware::{check_head, Layered, Middleware, MiddlewareStack, Next};
+use crate::http_utils::parser::RequestHead;
+use crate::http_utils::types::Response;
 use std::collections::HashMap;
-use crate::http_utils::status::ParseError;
-use crate::http_utils::request::request_logic::sanitize_path;
+use std::fmt;
+use std::sync::Arc;
+use crate::http_utils::request::request_logic::{is_api_request, path_segments};
 use crate::routes::web;
-use crate::api::v1;
-use crate::http_utils::response;
-
-
-pub fn route_request(request_method: &str, path: &str, body: UniversalBody, query_map: HashMap<String, String>) -> Result<Vec<u8>, ParseError > { //Route Error no>?
-    //MATCH FOR BOTH API AND HTTP
-    let response: Vec<u8> = match (request_method, sanitize_path(path)) {
-        (_, Some("400")) => web::handle_400(),
-        ("GET", Some("/api/v1/users")) => v1::users::handle_get_user(query_map),
-        ("POST", Some("/api/v1/posts")) => v1::posts::handle_post_post(query_map, body),
-        ("GET", Some("/")) => web::handle_home(),
-        ("GET", Some("/about")) => web::handle_about(),
-        ("GET",  Some("/submit")) => web::handle_submit_get(query_map),
-        ("POST", Some("/submit/json")) => web::submit_post_handler(query_map, body),
-        ("POST", Some("/submit/text")) => web::submit_post_handler(query_map, body),
-        ("POST", Some("/submit/binary")) => web::submit_post_handler(query_map, body),
-        ("GET", Some("/chunky")) => web::handle_transfer_chunk_encoding(),
-        ("GET", Some(path)) => match response::serve_file(path) {
-            Ok(response) => response,
-            Err(e) => {
-                println!("Error: {:?}", e);
-                return Err(e);
+
+pub type PathParams = HashMap<String, String>;
+type MethodHandlers = HashMap<String, Arc<dyn Handler>>;
+type RouteEntry = (String, String, Arc<dyn Handler>);
+
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    InvalidPattern(String),
+    Conflict { method: String, pattern: String },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern(pattern) => write!(f, "invalid route pattern: {}", pattern),
+            RouteError::Conflict { method, pattern } => write!(f, "route {} {} conflicts with an existing route", method, pattern),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+// One node per path segment. Static children win over a `:param` child,
+// which wins over a `*wildcard`.
+#[derive(Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, MethodHandlers)>,
+    handlers: MethodHandlers,
+}
+
+pub enum RouteMatch<'a> {
+    Found(&'a Arc<dyn Handler>, PathParams),
+    // The path exists but not for this method. Holds every method it does accept.
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+#[derive(Default)]
+pub struct Router {
+    root: Node,
+    middleware: MiddlewareStack,
+}
+
+// `/users/:id` -> ["users", ":id"]. Empty segments are ignored, so a trailing slash doesn't matter.
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|segment| !segment.is_empty()).collect()
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    // Registers `handler` for `method` and `pattern`. Patterns are made of static
+    // segments, `:name` segments matching exactly one segment, and an optional
+    // trailing `*name` matching the rest of the path.
+    pub fn route(self, method: &str, pattern: &str, handler: impl Handler) -> Result<Self, RouteError> {
+        self.insert(method, pattern, Arc::new(handler))
+    }
+
+    // Wraps every request this router handles, including its 404/405 answers.
+    // Middleware runs in the order it was added, the first one outermost.
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+        self
+    }
+
+    // Mounts every route of `router` under `prefix`. The nested router's own
+    // middleware only runs for its routes.
+    pub fn nest(mut self, prefix: &str, router: Router) -> Result<Self, RouteError> {
+        let prefix = prefix.trim_end_matches('/');
+        let mut routes = Vec::new();
+        collect_routes(&router.root, String::new(), &mut routes);
+        for (method, pattern, handler) in routes {
+            let handler: Arc<dyn Handler> = if router.middleware.is_empty() {
+                handler
+            } else {
+                Arc::new(Layered { stack: router.middleware.clone(), endpoint: handler })
+            };
+            self = self.insert(&method, &format!("{}{}", prefix, pattern), handler)?;
+        }
+        Ok(self)
+    }
+
+    fn insert(mut self, method: &str, pattern: &str, handler: Arc<dyn Handler>) -> Result<Self, RouteError> {
+        if !pattern.starts_with('/') {
+            return Err(RouteError::InvalidPattern(pattern.to_string()));
+        }
+        let conflict = || RouteError::Conflict { method: method.to_string(), pattern: pattern.to_string() };
+        let parts = segments(pattern);
+
+        let mut node = &mut self.root;
+        for (i, segment) in parts.iter().enumerate() {
+            if let Some(name) = segment.strip_prefix('*') {
+                if name.is_empty() || i != parts.len() - 1 {
+                    return Err(RouteError::InvalidPattern(pattern.to_string()));
+                }
+                let (existing, handlers) = node.wildcard.get_or_insert_with(|| (name.to_string(), HashMap::new()));
+                if existing != name || handlers.contains_key(method) {
+                    return Err(conflict());
+                }
+                handlers.insert(method.to_string(), handler);
+                return Ok(self);
             }
-        },
 
-        (_, None) => web::handle_403(),
-        _ => web::handle_404(),
+            node = if let Some(name) = segment.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(RouteError::InvalidPattern(pattern.to_string()));
+                }
+                let (existing, child) = node.param.get_or_insert_with(|| (name.to_string(), Box::default()));
+                if existing != name {
+                    return Err(conflict());
+                }
+                child
+            } else {
+                node.statics.entry(segment.to_string()).or_default()
+            };
+        }
+
+        if node.handlers.contains_key(method) {
+            return Err(conflict());
+        }
+        node.handlers.insert(method.to_string(), handler);
+        Ok(self)
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler) -> Result<Self, RouteError> {
+        self.route("GET", pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler) -> Result<Self, RouteError> {
+        self.route("POST", pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler) -> Result<Self, RouteError> {
+        self.route("PUT", pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler) -> Result<Self, RouteError> {
+        self.route("DELETE", pattern, handler)
+    }
+
+    // `parts` are the decoded segments of the path, as `path_segments` gives them
+    pub fn find(&self, method: &str, parts: &[String]) -> RouteMatch<'_> {
+        let mut params = PathParams::new();
+        if let Some(handler) = find_in(&self.root, parts, method, &mut params) {
+            return RouteMatch::Found(handler, params);
+        }
+        // HEAD is answered by the GET route unless one is registered for it. The
+        // body is dropped when the response is sent (RFC 9110 section 9.3.2).
+        if method == "HEAD"
+            && let Some(handler) = find_in(&self.root, parts, "GET", &mut params) {
+            return RouteMatch::Found(handler, params);
+        }
+
+        let mut allowed = Vec::new();
+        allowed_in(&self.root, parts, &mut allowed);
+        if allowed.is_empty() {
+            return RouteMatch::NotFound;
+        }
+        if allowed.iter().any(|allowed| allowed == "GET") && !allowed.iter().any(|allowed| allowed == "HEAD") {
+            allowed.push("HEAD".to_string());
+        }
+        if !allowed.iter().any(|allowed| allowed == "OPTIONS") {
+            allowed.push("OPTIONS".to_string());
+        }
+        allowed.sort_by_key(|method| METHODS.iter().position(|known| known == method).unwrap_or(METHODS.len()));
+        RouteMatch::MethodNotAllowed(allowed)
+    }
+
+    // What the middleware of this router, and of the route `head` is for, answer to
+    // the head of a request whose body hasn't been read, see `Middleware::check_head`
+    pub fn check_head(&self, head: &RequestHead, parts: &[String]) -> Option<Response> {
+        if let Some(response) = check_head(&self.middleware, head) {
+            return Some(response);
+        }
+        match self.find(head.method, parts) {
+            RouteMatch::Found(handler, _) => handler.check_head(head),
+            _ => None,
+        }
+    }
+
+    pub async fn route_request(&self, mut request: Request) -> HandlerResult {
+        let is_api = is_api_request(&request.path);
+        // Routed on the raw path, so `%2F` can't add a segment
+        let route = match path_segments(&request.path)? {
+            Some(parts) => self.find(&request.method, &parts),
+            None => return Next::new(self.middleware.clone(), Arc::new(sync(move |_| Ok(web::handle_403(is_api))))).run(request).await,
+        };
+
+        let endpoint: Arc<dyn Handler> = match route {
+            RouteMatch::Found(handler, params) => {
+                log_debug!("Path params: {:?}", params);
+                request.params = params;
+                handler.clone()
+            }
+            RouteMatch::MethodNotAllowed(allowed) if request.method == "OPTIONS" => {
+                let allow = allowed.join(", ");
+                Arc::new(sync(move |_| Ok(web::handle_options(&allow))))
+            }
+            RouteMatch::MethodNotAllowed(allowed) => {
+                let allow = allowed.join(", ");
+                Arc::new(sync(move |_| Ok(web::handle_405(&allow, is_api))))
+            }
+            RouteMatch::NotFound => Arc::new(sync(move |_| Ok(web::handle_404(is_api)))),
+        };
+        Next::new(self.middleware.clone(), endpoint).run(request).await
+    }
+}
+
+fn find_in<'a>(node: &'a Node, parts: &[String], method: &str, params: &mut PathParams) -> Option<&'a Arc<dyn Handler>> {
+    let Some((first, rest)) = parts.split_first() else {
+        return node.handlers.get(method);
     };
-    Ok(response)
-}
\ No newline at end of file
+
+    if let Some(child) = node.statics.get(first)
+        && let Some(handler) = find_in(child, rest, method, params) {
+        return Some(handler);
+    }
+
+    if let Some((name, child)) = &node.param
+        && let Some(handler) = find_in(child, rest, method, params) {
+        params.insert(name.clone(), first.clone());
+        return Some(handler);
+    }
+
+    if let Some((name, handlers)) = &node.wildcard
+        && let Some(handler) = handlers.get(method) {
+        params.insert(name.clone(), parts.join("/"));
+        return Some(handler);
+    }
+
+    None
+}
+
+// Collects the methods of every route whose pattern matches `parts`
+fn allowed_in(node: &Node, parts: &[String], allowed: &mut Vec<String>) {
+    let mut add = |handlers: &MethodHandlers| {
+        for method in handlers.keys() {
+            if !allowed.contains(method) {
+                allowed.push(method.clone());
+            }
+        }
+    };
+
+    let Some((first, rest)) = parts.split_first() else {
+        add(&node.handlers);
+        return;
+    };
+
+    if let Some((_, handlers)) = &node.wildcard {
+        add(handlers);
+    }
+    if let Some(child) = node.statics.get(first) {
+        allowed_in(child, rest, allowed);
+    }
+    if let Some((_, child)) = &node.param {
+        allowed_in(child, rest, allowed);
+    }
+}
+
+// Rebuilds the (method, pattern, handler) list a router was made from
+fn collect_routes(node: &Node, pattern: String, routes: &mut Vec<RouteEntry>) {
+    let here = if pattern.is_empty() { "/".to_string() } else { pattern.clone() };
+    for (method, handler) in &node.handlers {
+        routes.push((method.clone(), here.clone(), handler.clone()));
+    }
+    for (segment, child) in &node.statics {
+        collect_routes(child, format!("{}/{}", pattern, segment), routes);
+    }
+    if let Some((name, child)) = &node.param {
+        collect_routes(child, format!("{}/:{}", pattern, name), routes);
+    }
+    if let Some((name, handlers)) = &node.wildcard {
+        for (method, handler) in handlers {
+            routes.push((method.clone(), format!("{}/*{}", pattern, name), handler.clone()));
+        }
+    }
+}
@@ -1,48 +1,27 @@
 use tokio::net::TcpStream;
-use tokio::io::AsyncWriteExt;
-use std::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
 use std::path::Path;
 use crate::http_utils::status::Status;
 use crate::http_utils::status::ParseError;
-use crate::http_utils::types::{ApiBody, Response};
-use serde_json::Value;
-use std::collections::HashMap;
+use crate::http_utils::types::{ApiBody, Body, BodyStream, Response};
+use serde::Serialize;
+use serde_json::json;
 
 
-pub fn serve_file(file_path: &str) -> Result<Vec<u8>, ParseError> {
-    let base = Path::new("static");
+// Serves `file_path` from under the `base` directory
+pub fn serve_file(base: &Path, file_path: &str) -> Result<Response, ParseError> {
     let path = base.join(file_path.trim_start_matches("/"));
 
     if !path.starts_with(base) {
-        return match build_response(Status::Forbidden, "text/html", b"FORBIDDEN") {
-            Ok(response) => Ok(response.convert_to_vec()),
-            Err(e) => {
-                println!("Error: {:?}", e);
-                return Err(e);
-            }
-        }
+        return Ok(build_response(Status::Forbidden, "text/html", b"FORBIDDEN"));
     }
 
-    let content_type = get_content_type(file_path);
-
-    match fs::read(&path) {
-        Ok(contents) => {
-            match build_response(Status::Ok, content_type, &contents) {
-                Ok(response) => Ok(response.convert_to_vec()),
-                Err(e) => {
-                    println!("Error: {:?}", e);
-                    return Err(e);
-                }
-            }
-        }
-        Err(_) => {
-            match build_response(Status::NotFound, "404 Not Found", b"The requested file was not found") {
-                Ok(response) => Ok(response.convert_to_vec()),
-                Err(e) => {
-                    println!("Error: {:?}", e);
-                    return Err(e);
-                }
-            }
+    match Response::ok().file(&path) {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            log_debug!("Not serving {}: {}", path.display(), e);
+            Ok(build_response(Status::NotFound, "text/html", b"The requested file was not found"))
         }
     }
 }
@@ -58,75 +37,204 @@ pub fn get_content_type(file_path: &str) -> &str {
     }
 }
 
-fn build_response_header(status: Status, content_type: &str, body: &[u8]) -> HashMap<String, String> {
-    let mut response_header = HashMap::new();
-    response_header.insert("Content-Type".to_string(), content_type.to_string());
-    response_header.insert("Content-Length".to_string(), body.len().to_string());
-    response_header.insert("Status".to_string(), String::from_utf8_lossy(status.line()).to_string());
-    response_header
+pub fn build_response(status: Status, content_type: &str, body: &[u8]) -> Response {
+    Response::new(status).content_type(content_type).body(body)
 }
 
-pub fn build_response(status: Status, content_type: &str, body: &[u8]) -> Result<Response, ParseError> {
-    let status_line = String::from_utf8_lossy(status.line()).to_string();
-    let response_header = build_response_header(status, content_type, body);
-    let response = Response {
-        status: status_line,
-        headers: response_header,
-        body: body.to_vec(),
-    };
-    println!("Response.status: {}", response.status);
-    println!("Response.headers: {:?}", response.headers);
-    println!("Response.body: {}", String::from_utf8_lossy(&response.body));
-    Ok(response)
-}
-
-pub fn api_response(status: Status, body: &[u8]) -> Vec<u8> {
-    let body: Value = serde_json::from_slice(body).unwrap();
-
+// `body` wrapped as `{"status": ..., "body": ...}`. One that can't be serialized gives a 500.
+pub fn api_response<T: Serialize + ?Sized>(status: Status, body: &T) -> Response {
     let response_body = ApiBody {
-        status: String::from_utf8_lossy(status.line()).to_string(),
-        body: body,
+        status: status.line(),
+        body,
     };
-    let res = serde_json::to_vec(&response_body).unwrap();
-    let response = build_response(status, "application/json", &res).unwrap();
-    response.convert_to_vec()
+    Response::new(status).json(&response_body)
 }
 
-pub fn html_response(status: Status, title: &str, message: &str) -> Vec<u8> {
+pub fn html_response(status: Status, title: &str, message: &str) -> Response {
     let body = format!(
         "<html><body><h1>{}</h1><p>{}</p></body></html>",
         title, message
     );
-    let response = build_response(status, "text/html", body.as_bytes()).unwrap();
-    response.convert_to_vec()
+    Response::new(status).html(body)
+}
+
+// Anything a request can be answered with
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+// An HTML error page with the status the error maps to
+impl IntoResponse for ParseError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        html_response(status, status.reason(), &escape_html(&public_message(&self)))
+    }
 }
 
-pub async fn send_response(stream: &mut TcpStream, response: Vec<u8>) -> std::io::Result<()> {
+// What the client is told about `error`. A server error only gets its reason
+// phrase, its detail may be internal and is for the log.
+fn public_message(error: &ParseError) -> String {
+    let status = error.status();
+    if status.is_server_error() {
+        status.reason().to_string()
+    } else {
+        error.to_string()
+    }
+}
+
+// The same error as `{"error": {"code", "reason", "message"}}`, for API clients
+pub struct ApiError(pub ParseError);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        api_error(self.0.status(), &public_message(&self.0))
+    }
+}
+
+// An error with `status` in the shape `ApiError` gives, for answers that don't start from a `ParseError`
+pub fn api_error(status: Status, message: &str) -> Response {
+    let body = json!({
+        "error": {
+            "code": status.code(),
+            "reason": status.reason(),
+            "message": message,
+        }
+    });
+    api_response(status, &body)
+}
+
+// For putting text that came from the client into a page
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn streaming_response(status: Status, content_type: &str, body: BodyStream) -> Response {
+    Response::new(status).content_type(content_type).stream(body)
+}
+
+// Sets `Content-Length`, or `Transfer-Encoding` for a stream, from the body. A 1xx
+// or 204 has neither and no body (RFC 9110 section 8.6). A 304 keeps what the
+// handler set, since it describes the response it stands in for (section 15.4.5).
+// HTTP/1.0 has no chunked coding (RFC 9112 section 6.1), a stream is sent as is
+// there and ended by closing the connection.
+fn set_framing(response: &mut Response, http_1_0: bool) {
+    let status = response.status.code();
+    if response.status.is_informational() || status == 204 {
+        response.headers.remove("Content-Length");
+        response.headers.remove("Transfer-Encoding");
+        response.body = Body::Bytes(Vec::new());
+        return;
+    }
+    if status == 304 {
+        return;
+    }
+    match &response.body {
+        Body::Bytes(bytes) => {
+            response.headers.remove("Transfer-Encoding");
+            response.headers.insert("Content-Length", bytes.len().to_string());
+        }
+        Body::File(_, len) => {
+            response.headers.remove("Transfer-Encoding");
+            response.headers.insert("Content-Length", len.to_string());
+        }
+        Body::Stream(_) if http_1_0 => {
+            response.headers.remove("Content-Length");
+            response.headers.remove("Transfer-Encoding");
+            response.headers.remove("Keep-Alive");
+            response.headers.insert("Connection", "close");
+        }
+        Body::Stream(_) => {
+            response.headers.remove("Content-Length");
+            response.headers.insert("Transfer-Encoding", "chunked");
+        }
+    }
+}
+
+// Writes `response` out. With `head_only`, for a HEAD request, the framing
+// headers are those the body would get but the body itself isn't sent. With
+// `http_1_0` a stream isn't chunked, the connection has to be closed after it.
+pub async fn send_response(stream: &mut TcpStream, mut response: Response, head_only: bool, http_1_0: bool) -> std::io::Result<()> {
+    set_framing(&mut response, http_1_0);
     log_response(&response);
-    stream.write_all(&response).await.unwrap();
+    let mut head = response.head_bytes();
+    if head_only || response.status.code() == 304 {
+        stream.write_all(&head).await?;
+        return stream.flush().await;
+    }
 
+    match response.body {
+        Body::Bytes(bytes) => {
+            head.extend_from_slice(&bytes);
+            stream.write_all(&head).await?;
+        }
+        Body::File(file, len) => {
+            stream.write_all(&head).await?;
+            let mut file = tokio::fs::File::from_std(file).take(len);
+            let sent = tokio::io::copy(&mut file, stream).await?;
+            // The file shrank since it was opened, the promised length can't be kept
+            if sent < len {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file ended before its length"));
+            }
+        }
+        Body::Stream(mut body) => {
+            stream.write_all(&head).await?;
+            let mut body_length = 0;
+            while let Some(chunk) = body.next().await {
+                // A zero-sized chunk would terminate the body early
+                if chunk.is_empty() {
+                    continue;
+                }
+                body_length += chunk.len();
+                if http_1_0 {
+                    stream.write_all(&chunk).await?;
+                } else {
+                    stream.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+                    stream.write_all(&chunk).await?;
+                    stream.write_all(b"\r\n").await?;
+                }
+                stream.flush().await?;
+            }
+            if !http_1_0 {
+                stream.write_all(b"0\r\n\r\n").await?;
+            }
+            log_debug!("Streamed body length: {} bytes", body_length);
+        }
+    }
     // Send the response right away because it might stay in the buffer
-    stream.flush().await.unwrap();
-    Ok(())
-}
-
-pub fn log_response(response: &[u8]) {
-    // Find the end of headers (\r\n\r\n)
-    if let Some(pos) = response.windows(4).position(|w| w == b"\r\n\r\n") {
-        println!("=== Response Headers ===");
-        println!("{}", String::from_utf8_lossy(&response[..pos]));
-        
-        let body_start = pos + 4;
-        println!("Body length: {} bytes", response.len() - body_start);
-        
-        // Print text bodies
-        if let Ok(text) = String::from_utf8(response[body_start..].to_vec()) {
-            if !text.is_empty() {
-                println!("Body:\n{}", text);
+    stream.flush().await
+}
+
+pub fn log_response(response: &Response) {
+    log_debug!("=== Response Headers ===");
+    log_debug!("{}", String::from_utf8_lossy(&response.head_bytes()).trim_end());
+    match &response.body {
+        Body::Bytes(bytes) => {
+            log_debug!("Body length: {} bytes", bytes.len());
+            // Print text bodies
+            if let Ok(text) = std::str::from_utf8(bytes)
+                && !text.is_empty() {
+                log_debug!("Body:\n{}", text);
             }
         }
-    } else {
-        println!("[Malformed HTTP response]");
+        Body::File(_, len) => log_debug!("File body: {} bytes", len),
+        Body::Stream(_) => log_debug!("Streamed body"),
     }
-    println!("================================");
+    log_debug!("================================");
 }
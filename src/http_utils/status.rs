@@ -1,42 +1,268 @@
 use serde::Serialize;
+use std::fmt;
 
-#[derive(Clone)]
-#[derive(Serialize)]
-pub enum Status {
-    Ok = 200,
-    Created = 201,
-    NoContent = 204,
-    NotFound = 404,
-    BadRequest = 400,
-    Unauthorized = 401,
-    Forbidden = 403,
-    RequestTimeout = 408,
-    MethodNotAllowed = 405,
-    InternalError = 500,
-    ServiceUnavailable = 503,
-}
-
-#[derive(PartialEq)]
+// Declares `Status` from a table of code, variant and reason phrase
+macro_rules! statuses {
+    ($($code:literal $name:ident $reason:literal;)+) => {
+        // Every status code in the IANA registry (RFC 9110 section 15 and the RFCs
+        // that registered the others), and `Custom` for any other three-digit code.
+        // `from_code` checks the code, a `Custom` made by hand is only checked when sent.
+        #[derive(Clone, Copy, Debug)]
+        pub enum Status {
+            $($name,)+
+            Custom(u16),
+        }
+
+        impl Status {
+            pub fn code(&self) -> u16 {
+                match self {
+                    $(Self::$name => $code,)+
+                    Self::Custom(code) => *code,
+                }
+            }
+
+            // The reason phrase, as sent in the status line. A custom code has none,
+            // which the status line allows (RFC 9112 section 4).
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(Self::$name => $reason,)+
+                    Self::Custom(_) => "",
+                }
+            }
+
+            // The variant for `code`, `Custom` if it isn't registered. Status codes
+            // have three digits (RFC 9110 section 15), anything else is `None`.
+            pub fn from_code(code: u16) -> Option<Status> {
+                match code {
+                    $($code => Some(Self::$name),)+
+                    100..=999 => Some(Self::Custom(code)),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    100 Continue "Continue";
+    101 SwitchingProtocols "Switching Protocols";
+    102 Processing "Processing";
+    103 EarlyHints "Early Hints";
+    200 Ok "OK";
+    201 Created "Created";
+    202 Accepted "Accepted";
+    203 NonAuthoritativeInformation "Non-Authoritative Information";
+    204 NoContent "No Content";
+    205 ResetContent "Reset Content";
+    206 PartialContent "Partial Content";
+    207 MultiStatus "Multi-Status";
+    208 AlreadyReported "Already Reported";
+    226 ImUsed "IM Used";
+    300 MultipleChoices "Multiple Choices";
+    301 MovedPermanently "Moved Permanently";
+    302 Found "Found";
+    303 SeeOther "See Other";
+    304 NotModified "Not Modified";
+    305 UseProxy "Use Proxy";
+    307 TemporaryRedirect "Temporary Redirect";
+    308 PermanentRedirect "Permanent Redirect";
+    400 BadRequest "Bad Request";
+    401 Unauthorized "Unauthorized";
+    402 PaymentRequired "Payment Required";
+    403 Forbidden "Forbidden";
+    404 NotFound "Not Found";
+    405 MethodNotAllowed "Method Not Allowed";
+    406 NotAcceptable "Not Acceptable";
+    407 ProxyAuthenticationRequired "Proxy Authentication Required";
+    408 RequestTimeout "Request Timeout";
+    409 Conflict "Conflict";
+    410 Gone "Gone";
+    411 LengthRequired "Length Required";
+    412 PreconditionFailed "Precondition Failed";
+    413 ContentTooLarge "Content Too Large";
+    414 UriTooLong "URI Too Long";
+    415 UnsupportedMediaType "Unsupported Media Type";
+    416 RangeNotSatisfiable "Range Not Satisfiable";
+    417 ExpectationFailed "Expectation Failed";
+    421 MisdirectedRequest "Misdirected Request";
+    422 UnprocessableContent "Unprocessable Content";
+    423 Locked "Locked";
+    424 FailedDependency "Failed Dependency";
+    425 TooEarly "Too Early";
+    426 UpgradeRequired "Upgrade Required";
+    428 PreconditionRequired "Precondition Required";
+    429 TooManyRequests "Too Many Requests";
+    431 RequestHeaderFieldsTooLarge "Request Header Fields Too Large";
+    451 UnavailableForLegalReasons "Unavailable For Legal Reasons";
+    500 InternalServerError "Internal Server Error";
+    501 NotImplemented "Not Implemented";
+    502 BadGateway "Bad Gateway";
+    503 ServiceUnavailable "Service Unavailable";
+    504 GatewayTimeout "Gateway Timeout";
+    505 HttpVersionNotSupported "HTTP Version Not Supported";
+    506 VariantAlsoNegotiates "Variant Also Negotiates";
+    507 InsufficientStorage "Insufficient Storage";
+    508 LoopDetected "Loop Detected";
+    510 NotExtended "Not Extended";
+    511 NetworkAuthenticationRequired "Network Authentication Required";
+}
+
+impl Status {
+    // A code without three digits can't go in a status line, it is sent as a 500
+    pub fn line(&self) -> String {
+        let status = match Status::from_code(self.code()) {
+            Some(status) => status,
+            None => {
+                log_error!("Status code {} is not three digits, sending 500 instead", self.code());
+                Status::InternalServerError
+            }
+        };
+        format!("HTTP/1.1 {} {}", status.code(), status.reason())
+    }
+
+    // 1xx
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    // 2xx
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    // 3xx
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    // 4xx
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    // 5xx
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+// Statuses are the same when their codes are, so `Custom(404)` is `NotFound`
+impl PartialEq for Status {
+    fn eq(&self, other: &Self) -> bool {
+        self.code() == other.code()
+    }
+}
+
+impl Eq for Status {}
+
+// `404 Not Found`
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason() {
+            "" => write!(f, "{}", self.code()),
+            reason => write!(f, "{} {}", self.code(), reason),
+        }
+    }
+}
+
+// Serialized as its number
+impl Serialize for Status {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.code())
+    }
+}
+
+// Everything that can go wrong between reading a request and answering it.
+// The `String`s say what was wrong, for the log and the error page.
+#[derive(Clone, PartialEq)]
 #[derive(Debug)]
 pub enum ParseError {
-    MalformedRequest,
+    MalformedRequest(String),
+    BadRequestLine(String),
+    UnsupportedVersion(String),
+    InvalidHeader(String),
+    UnsupportedMediaType(String),
+    // Well-formed, but not what the handler asked for, e.g. a JSON body missing a field
+    UnprocessableContent(String),
+    // Over one of the limits in `ServerOptions`
+    RequestLineTooLong,
+    HeadersTooLarge,
+    // Holds the limit that was passed
+    BodyTooLarge(usize),
+    // Ambiguous framing a proxy in front of us might read differently (RFC 9112 sections 5 and 6)
+    ContentLengthWithTransferEncoding,
+    ConflictingContentLength,
+    InvalidContentLength,
+    UnsupportedTransferEncoding,
+    WhitespaceBeforeColon,
+    ObsoleteLineFolding,
+    // The client took longer than the header or body timeout
+    Timeout,
+    // The client went away, there is no one left to answer
     ConnectionAborted,
+    Io(String),
+    // The server couldn't keep part of the request, e.g. an upload on a full disk.
+    // Unlike `Io` the client is still there and gets a 500.
+    Storage(String),
+    // A handler failed in a way the client can't do anything about
+    Handler(String),
 }
 
-impl Status {
-    pub fn line(&self) -> &'static [u8] {
+impl ParseError {
+    // The status a request failing with this error is answered with
+    pub fn status(&self) -> Status {
         match self {
-            Self::Ok => b"HTTP/1.1 200 OK",
-            Self::Created => b"HTTP/1.1 201 CREATED",
-            Self::NoContent => b"HTTP/1.1 204 NO CONTENT",
-            Self::NotFound => b"HTTP/1.1 404 NOT FOUND",
-            Self::BadRequest => b"HTTP/1.1 400 BAD REQUEST",
-            Self::Unauthorized => b"HTTP/1.1 401 UNAUTHORIZED",
-            Self::Forbidden => b"HTTP/1.1 403 FORBIDDEN",
-            Self::RequestTimeout => b"HTTP/1.1 408 REQUEST TIMEOUT",
-            Self::MethodNotAllowed => b"HTTP/1.1 405 METHOD NOT ALLOWED",
-            Self::InternalError => b"HTTP/1.1 500 INTERNAL SERVER ERROR",
-            Self::ServiceUnavailable => b"HTTP/1.1 503 SERVICE UNAVAILABLE",
+            Self::MalformedRequest(_)
+            | Self::BadRequestLine(_)
+            | Self::InvalidHeader(_)
+            | Self::ContentLengthWithTransferEncoding
+            | Self::ConflictingContentLength
+            | Self::InvalidContentLength
+            | Self::UnsupportedTransferEncoding
+            | Self::WhitespaceBeforeColon
+            | Self::ObsoleteLineFolding => Status::BadRequest,
+            Self::UnsupportedVersion(_) => Status::HttpVersionNotSupported,
+            Self::UnsupportedMediaType(_) => Status::UnsupportedMediaType,
+            Self::UnprocessableContent(_) => Status::UnprocessableContent,
+            Self::RequestLineTooLong => Status::UriTooLong,
+            Self::HeadersTooLarge => Status::RequestHeaderFieldsTooLarge,
+            Self::BodyTooLarge(_) => Status::ContentTooLarge,
+            Self::Timeout | Self::ConnectionAborted => Status::RequestTimeout,
+            Self::Io(_) | Self::Storage(_) | Self::Handler(_) => Status::InternalServerError,
         }
     }
-}
\ No newline at end of file
+
+    // Whether the client is still there to be answered
+    pub fn is_disconnect(&self) -> bool {
+        matches!(self, Self::ConnectionAborted | Self::Io(_))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MalformedRequest(reason) => write!(f, "malformed request: {}", reason),
+            Self::BadRequestLine(line) => write!(f, "bad request line: {:?}", line),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported HTTP version: {}", version),
+            Self::InvalidHeader(reason) => write!(f, "invalid header: {}", reason),
+            Self::UnsupportedMediaType(media_type) => write!(f, "unsupported media type: {}", media_type),
+            Self::UnprocessableContent(reason) => write!(f, "unprocessable content: {}", reason),
+            Self::RequestLineTooLong => write!(f, "request line is too long"),
+            Self::HeadersTooLarge => write!(f, "request headers are too large"),
+            Self::BodyTooLarge(limit) => write!(f, "request body is over {} bytes", limit),
+            Self::ContentLengthWithTransferEncoding => write!(f, "both Content-Length and Transfer-Encoding were sent"),
+            Self::ConflictingContentLength => write!(f, "conflicting Content-Length values"),
+            Self::InvalidContentLength => write!(f, "invalid Content-Length"),
+            Self::UnsupportedTransferEncoding => write!(f, "unsupported Transfer-Encoding"),
+            Self::WhitespaceBeforeColon => write!(f, "whitespace between a header name and its colon"),
+            Self::ObsoleteLineFolding => write!(f, "folded header line"),
+            Self::Timeout => write!(f, "timed out waiting for the request"),
+            Self::ConnectionAborted => write!(f, "connection closed by the client"),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Storage(e) => write!(f, "could not store the request: {}", e),
+            Self::Handler(e) => write!(f, "handler failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
\ No newline at end of file
@@ -1,28 +1,183 @@
-use std::collections::HashMap;
-use serde_json::Value as JsonValue;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use crate::http_utils::headers::HeaderMap;
+use crate::http_utils::query::QueryMap;
+use crate::http_utils::request::multipart::Multipart;
+use crate::http_utils::parser::{HeadParser, RequestHead};
+use crate::http_utils::cookie::Cookie;
+use crate::http_utils::response::get_content_type;
+use crate::http_utils::status::Status;
 use serde::Serialize;
 use serde_json::Value;
+use tokio_stream::Stream;
+
+// A request as read off the wire: the head, already parsed, and the raw body.
+// A chunked body is already decoded, its trailer fields are kept apart.
+#[derive(Debug)]
+pub struct RawRequest {
+    pub head: Vec<u8>,
+    parser: HeadParser,
+    pub body: Vec<u8>,
+    pub trailers: Vec<(String, String)>,
+    // A multipart/form-data body, parsed while it was read. `body` is left empty then.
+    pub multipart: Option<Multipart>,
+}
+
+impl RawRequest {
+    // `parser` must have parsed all of `head`
+    pub fn new(head: Vec<u8>, parser: HeadParser) -> Self {
+        assert!(parser.is_done(), "RawRequest needs a completely parsed head");
+        RawRequest { head, parser, body: Vec::new(), trailers: Vec::new(), multipart: None }
+    }
+
+    pub fn head(&self) -> RequestHead<'_> {
+        self.parser.head(&self.head).expect("checked in RawRequest::new")
+    }
+}
 
 #[derive(Debug)]
 pub struct ApiRequest {
     pub path: String,
     pub method: String,
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    // Trailer fields of a chunked body
+    pub trailers: HeaderMap,
     pub body: UniversalBody,  
 }
 
+pub type BodyStream = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    // An open file and how much of it to send, read as it is written out
+    File(File, u64),
+    // Produced as it is sent and written with `Transfer-Encoding: chunked`,
+    // so it never has to be held in memory at once
+    Stream(BodyStream),
+}
+
+// What a handler answers with, built up like
+// `Response::new(Status::Created).header("Location", "/posts/1").json(&post)`.
+// It is only turned into bytes by the connection, which adds the framing
+// headers, `Date` and `Server`. Headers are sent in the order they were set.
 pub struct Response {
-    pub status: String,
-    pub headers: HashMap<String, String>,
-    pub body: Vec<u8>,
+    pub status: Status,
+    pub headers: HeaderMap,
+    pub body: Body,
 }
 
+impl Response {
+    // No headers and an empty body
+    pub fn new(status: Status) -> Self {
+        Response { status, headers: HeaderMap::new(), body: Body::Bytes(Vec::new()) }
+    }
+
+    pub fn ok() -> Self {
+        Response::new(Status::Ok)
+    }
+
+    pub fn status(mut self, status: Status) -> Self {
+        self.status = status;
+        self
+    }
+
+    // Replaces any value `name` already has
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    // Adds a value next to any `name` already has
+    pub fn append_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn content_type(self, content_type: &str) -> Self {
+        self.header("Content-Type", content_type)
+    }
+
+    // One `Set-Cookie` per cookie, they can't be combined into one line (RFC 6265 section 3)
+    pub fn cookie(self, cookie: Cookie) -> Self {
+        self.append_header("Set-Cookie", cookie.to_string())
+    }
+
+    // The body as is, without a `Content-Type`
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.default_content_type("text/plain; charset=utf-8").body(text.into())
+    }
+
+    pub fn html(self, html: impl Into<String>) -> Self {
+        self.default_content_type("text/html").body(html.into())
+    }
+
+    // A value that fails to serialize, like a map with non-string keys, is a mistake
+    // in the handler and turns the response into a 500
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(json) => self.default_content_type("application/json").body(json),
+            Err(e) => {
+                log_error!("Failed to serialize JSON response: {}", e);
+                Response::new(Status::InternalServerError)
+            }
+        }
+    }
+
+    // Sends the file at `path`, typed by its extension. It is opened here, so a
+    // missing file can still be answered otherwise, and read as it is sent.
+    pub fn file(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display())));
+        }
+        let mut response = self.default_content_type(get_content_type(&path.to_string_lossy()));
+        response.body = Body::File(file, metadata.len());
+        Ok(response)
+    }
+
+    pub fn stream(mut self, body: BodyStream) -> Self {
+        self.body = Body::Stream(body);
+        self
+    }
+
+    fn default_content_type(self, content_type: &str) -> Self {
+        match self.headers.contains("Content-Type") {
+            true => self,
+            false => self.content_type(content_type),
+        }
+    }
+
+    // The status line and headers as sent, ending with the empty line. Fields whose
+    // name or value would break the head are left out.
+    pub fn head_bytes(&self) -> Vec<u8> {
+        let mut head = self.status.line().into_bytes();
+        head.extend_from_slice(b"\r\n");
+        for (name, value) in self.headers.iter() {
+            if name.contains(['\r', '\n', ':']) || value.contains(['\r', '\n']) {
+                log_warn!("Leaving out invalid response header {:?}", name);
+                continue;
+            }
+            head.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        head.extend_from_slice(b"\r\n");
+        head
+    }
+}
 
 #[derive(Serialize)]
-pub struct ApiBody {
+pub struct ApiBody<T> {
     pub status: String,
-    pub body: JsonValue,
+    pub body: T,
 }
 
 #[derive(Debug)]
@@ -30,45 +185,24 @@ pub struct HttpRequest{
     pub method: String,
     pub path: String, 
     pub version: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    // Trailer fields of a chunked body
+    pub trailers: HeaderMap,
     pub body: UniversalBody,
 }
 
 #[derive(Debug)]
 pub enum ParsedRequest {
     Api(ApiRequest),
-    HTTP(HttpRequest),
-}
-
-impl Response {
-    pub fn convert_to_vec(&self) -> Vec<u8> {
-        match self {
-            Response { status, headers, body } => {
-                let header_vec = Response::header_to_vec(headers.clone());
-                let body_vec = body.clone();
-                let mut response_vec = Vec::new();
-                response_vec.extend_from_slice(status.as_bytes());
-                response_vec.extend_from_slice(b"\r\n");
-                response_vec.extend_from_slice(&header_vec);
-                response_vec.extend_from_slice(&body_vec);
-                response_vec
-            }
-        }
-    }
-
-    fn header_to_vec(header: HashMap<String, String>) -> Vec<u8> {
-        let mut header_vec = Vec::new();
-        for (key, value) in header {
-            header_vec.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
-        }
-        header_vec.extend_from_slice(b"\r\n");
-        header_vec
-    }
+    Http(HttpRequest),
 }
 
 #[derive(Debug, Clone)]
 pub enum UniversalBody {
     Json(Value),
     Binary(Vec<u8>),
-    Text(String)
+    Text(String),
+    // The fields of an `application/x-www-form-urlencoded` body, already decoded
+    Form(QueryMap),
+    Multipart(Multipart),
 }
\ No newline at end of file
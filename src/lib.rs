@@ -0,0 +1,9 @@
+#[macro_use]
+pub mod log;
+pub mod http_utils;
+pub mod routes;
+pub mod api;
+pub mod server;
+pub mod config;
+
+pub use server::{shutdown_signal, Server, ServerBuilder, ServerHandle, ServerOptions};
@@ -1 +1,49 @@
-pub mod web;
\ No newline at end of file
+pub mod web;
+
+use crate::api::v1;
+use crate::http_utils::middleware::Logger;
+use crate::http_utils::request::handler::{accepts, sync};
+use crate::http_utils::request::request_logic::is_api_request;
+use crate::http_utils::request::router::{RouteError, Router};
+use crate::http_utils::response;
+use std::path::PathBuf;
+
+pub fn api_v1_router() -> Result<Router, RouteError> {
+    Router::new()
+        .get("/users", sync(|req| Ok(v1::users::handle_get_user(req.query_map))))?
+        .get("/users/:id", sync(|req| Ok(v1::users::handle_get_user_by_id(req.params, req.query_map))))?
+        .post(
+            "/posts",
+            accepts(&["application/json", "application/*+json"], sync(|req| Ok(v1::posts::handle_post_post(req.extract()?)))),
+        )
+}
+
+// Every route served by the bundled site and API, with static files from ./static
+pub fn app_router() -> Result<Router, RouteError> {
+    app_router_with_root("static")
+}
+
+// Same as `app_router`, serving static files from `static_root`
+pub fn app_router_with_root(static_root: impl Into<PathBuf>) -> Result<Router, RouteError> {
+    let static_root = static_root.into();
+    let router = Router::new()
+        .nest("/api/v1", api_v1_router()?)?
+        .get("/400", sync(|_| Ok(web::handle_400())))?
+        .get("/", sync(|_| Ok(web::handle_home())))?
+        .get("/about", sync(|_| Ok(web::handle_about())))?
+        .get("/submit", sync(|req| Ok(web::handle_submit_get(req.query_map))))?
+        .post("/submit", sync(|req| Ok(web::submit_post_handler(req.query_map, req.body))))?
+        .post("/submit/json", sync(|req| Ok(web::submit_post_handler(req.query_map, req.body))))?
+        .post("/submit/text", sync(|req| Ok(web::submit_post_handler(req.query_map, req.body))))?
+        .post("/submit/binary", sync(|req| Ok(web::submit_post_handler(req.query_map, req.body))))?
+        .get("/chunky", sync(|_| Ok(web::handle_transfer_chunk_encoding())))?
+        // API paths no API route matched aren't files, they get the API's JSON 404
+        .get(
+            "/*path",
+            sync(move |req| match is_api_request(&req.path) {
+                true => Ok(web::handle_404(true)),
+                false => response::serve_file(&static_root, &req.params["path"]),
+            }),
+        )?;
+    Ok(router.layer(Logger))
+}
@@ -1,26 +1,43 @@
 use serde_json::Value;
-use std::collections::HashMap;
+use tokio_stream::StreamExt;
 
-use crate::http_utils::{response::{html_response}, status::Status, types::{UniversalBody}};
+use crate::http_utils::{query::QueryMap, request::multipart::Multipart, response::{api_error, build_response, escape_html, html_response, streaming_response}, status::Status, types::{Response, UniversalBody}};
 
 
-pub fn handle_about() -> Vec<u8> {
+pub fn handle_about() -> Response {
     html_response(Status::Ok, "ABOUT", "about")
 }
 
-pub fn handle_home() -> Vec<u8> {
+pub fn handle_home() -> Response {
     html_response(Status::Ok, "HOME", "HOME")
 }
 
-pub fn handle_404() -> Vec<u8> {
+// The routing answers below come as JSON for `/api/` paths, like every other API error
+
+pub fn handle_404(is_api: bool) -> Response {
+    if is_api {
+        return api_error(Status::NotFound, "no route matches this path");
+    }
     html_response(Status::NotFound, "NOT FOUND", "The requested file was not found")
 }
 
-pub fn handle_submit_get(query_map: HashMap<String, String>) -> Vec<u8> {
+pub fn handle_405(allow: &str, is_api: bool) -> Response {
+    if is_api {
+        return api_error(Status::MethodNotAllowed, &format!("the path only accepts {}", allow)).header("Allow", allow);
+    }
+    let body = "<html><body><h1>METHOD NOT ALLOWED</h1><p>METHOD NOT ALLOWED</p></body></html>";
+    build_response(Status::MethodNotAllowed, "text/html", body.as_bytes()).header("Allow", allow)
+}
+
+pub fn handle_options(allow: &str) -> Response {
+    Response::new(Status::NoContent).header("Allow", allow)
+}
+
+pub fn handle_submit_get(_query_map: QueryMap) -> Response {
     html_response(Status::Ok, "SUBMIT GET", "SUBMIT GET")
 }
 
-pub fn submit_post_handler(query_map: HashMap<String, String>, body: UniversalBody) -> Vec<u8> {
+pub fn submit_post_handler(_query_map: QueryMap, body: UniversalBody) -> Response {
     match body {
         UniversalBody::Json(json) => {
             handle_submit_post_json(json)
@@ -31,42 +48,86 @@ pub fn submit_post_handler(query_map: HashMap<String, String>, body: UniversalBo
         UniversalBody::Binary(binary) => {
             handle_submit_post_binary(binary)
         }
-        _ => {
-            html_response(Status::BadRequest, "UNKNOWN BODY TYPE", "UNKNOWN BODY TYPE")
+        UniversalBody::Form(fields) => {
+            handle_submit_post_form(fields)
+        }
+        UniversalBody::Multipart(multipart) => {
+            handle_submit_post_multipart(multipart)
         }
     }
 }
 
-pub fn handle_submit_post_json(json: Value) -> Vec<u8> {
-    let message = json.to_string();
-    html_response(Status::Ok, "SUBMIT POST", message.as_str())
+pub fn handle_submit_post_json(json: Value) -> Response {
+    html_response(Status::Ok, "SUBMIT POST", &escape_html(&json.to_string()))
 }
 
-pub fn handle_submit_post_text(body: String) -> Vec<u8> {
-    html_response(Status::Ok, "SUBMIT POST", body.as_str())
+pub fn handle_submit_post_text(body: String) -> Response {
+    html_response(Status::Ok, "SUBMIT POST", &escape_html(&body))
 }
 
-pub fn handle_submit_post_binary(body: Vec<u8>) -> Vec<u8> {
+// One `name: value` line per field, in the order they were sent
+pub fn handle_submit_post_form(fields: QueryMap) -> Response {
+    let lines: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", escape_html(name), escape_html(value)))
+        .collect();
+    html_response(Status::Ok, "SUBMIT POST", &lines.join("<br>"))
+}
+
+// Fields like a form, files by name, size and type. Uploads are deleted once this returns.
+pub fn handle_submit_post_multipart(multipart: Multipart) -> Response {
+    let lines: Vec<String> = multipart
+        .parts()
+        .iter()
+        .map(|part| match &part.filename {
+            Some(filename) => format!(
+                "{}: {} ({} bytes, {})",
+                escape_html(&part.name),
+                escape_html(filename),
+                part.len(),
+                escape_html(part.content_type.as_deref().unwrap_or("application/octet-stream"))
+            ),
+            None => format!("{}: {}", escape_html(&part.name), escape_html(part.text().unwrap_or("BINARY"))),
+        })
+        .collect();
+    html_response(Status::Ok, "SUBMIT POST", &lines.join("<br>"))
+}
+
+pub fn handle_submit_post_binary(_body: Vec<u8>) -> Response {
     html_response(Status::Ok, "SUBMIT POST", "BINARY")
 }
 
-pub fn handle_transfer_chunk_encoding() -> Vec<u8> {
-    // send_chunky_body(stream, body);
-    html_response(Status::Ok, "TRANSFER CHUNK ENCODING", "TRANSFER CHUNK ENCODING")
+pub fn handle_transfer_chunk_encoding() -> Response {
+    let mut chunks = vec!["<html><body><h1>TRANSFER CHUNK ENCODING</h1>".to_string()];
+    chunks.extend((1..=5).map(|i| format!("<p>CHUNK {}</p>", i)));
+    chunks.push("</body></html>".to_string());
+
+    let body = tokio_stream::iter(chunks).map(String::into_bytes);
+    streaming_response(Status::Ok, "text/html", Box::pin(body))
 }
 
-pub fn handle_500() -> Vec<u8> {
-    html_response(Status::InternalError, "INTERNAL SERVER ERROR", "SERVER ERROR")
+pub fn handle_500() -> Response {
+    html_response(Status::InternalServerError, "INTERNAL SERVER ERROR", "SERVER ERROR")
 }
 
-pub fn handle_403() -> Vec<u8> {
+pub fn handle_403(is_api: bool) -> Response {
+    if is_api {
+        return api_error(Status::Forbidden, "the path is not allowed");
+    }
     html_response(Status::Forbidden, "FORBIDDEN", "FORBIDDEN ACCESS")
 }
 
-pub fn handle_400() -> Vec<u8> {
+pub fn handle_400() -> Response {
     html_response(Status::BadRequest, "BAD_REQUEST", "BAD REQUEST")
 }
 
-pub fn handle_408() -> Vec<u8> {
+pub fn handle_408() -> Response {
     html_response(Status::RequestTimeout, "REQUEST TIMEOUT", "REQUEST TIMEOUT")
 }
+
+pub fn handle_417(is_api: bool) -> Response {
+    if is_api {
+        return api_error(Status::ExpectationFailed, "only 100-continue can be expected");
+    }
+    html_response(Status::ExpectationFailed, "EXPECTATION FAILED", "EXPECTATION FAILED")
+}
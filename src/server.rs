@@ -0,0 +1,515 @@
+use crate::http_utils::parser::parse_request_by_type;
+use crate::http_utils::request::reader::{read_request_body, read_request_head};
+use crate::http_utils::date::http_date;
+use crate::http_utils::response::send_response;
+use crate::http_utils::request::request_logic::{is_api_request, error_handler, reject_expectation, targets_api, wants_keep_alive};
+use crate::http_utils::request::extractor::extract_request_parts;
+use crate::http_utils::request::router::Router;
+use crate::http_utils::request::handler::{AppState, BoxFuture};
+use crate::http_utils::status::ParseError;
+use crate::http_utils::types::{Body, Response};
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{sleep, timeout, Duration};
+
+// How long a connection closed after an error is drained before it is dropped
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    // How long a client may take to send the request head
+    pub header_timeout: Duration,
+    // How long a client may take to send the request body
+    pub body_timeout: Duration,
+    // How long a keep-alive connection may sit idle between requests before it is closed
+    pub keep_alive_timeout: Duration,
+    // Requests served on one connection before it is closed, 0 for no limit
+    pub max_requests_per_connection: usize,
+    // Longest request line accepted, answered with 414 when over
+    pub max_request_line_bytes: usize,
+    // Largest request head (request line and headers) accepted, answered with 431 when over
+    pub max_header_bytes: usize,
+    // Most header lines accepted, answered with 431 when over
+    pub max_header_count: usize,
+    // Largest request body accepted, after dechunking, answered with 413 when over
+    pub max_body_bytes: usize,
+    // Largest single part of a multipart/form-data body, answered with 413 when over
+    pub max_part_bytes: usize,
+    // File parts bigger than this are written to `upload_dir` as they arrive instead of kept in memory
+    pub multipart_memory_bytes: usize,
+    // Where uploaded files are written, they are deleted once the request is answered
+    pub upload_dir: PathBuf,
+    // Size of each read from the socket
+    pub read_buffer_size: usize,
+    // How long open connections get to finish after shutdown starts
+    pub drain_timeout: Duration,
+    // Sent as the `Server` header, left out when `None`
+    pub server_header: Option<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(10),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 1000,
+            max_request_line_bytes: 8 * 1024,
+            max_header_bytes: 8 * 1024,
+            max_header_count: 100,
+            max_body_bytes: 10 * 1024 * 1024,
+            max_part_bytes: 10 * 1024 * 1024,
+            multipart_memory_bytes: 64 * 1024,
+            upload_dir: std::env::temp_dir(),
+            read_buffer_size: 1024,
+            drain_timeout: Duration::from_secs(30),
+            server_header: Some(concat!("http_serverrrrr/", env!("CARGO_PKG_VERSION")).to_string()),
+        }
+    }
+}
+
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            addrs: Vec::new(),
+            router: Router::new(),
+            options: ServerOptions::default(),
+            state: None,
+            shutdown_signal: None,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    addrs: Vec<String>,
+    router: Router,
+    options: ServerOptions,
+    state: Option<AppState>,
+    shutdown_signal: Option<BoxFuture<()>>,
+}
+
+impl ServerBuilder {
+    // Anything `TcpListener::bind` accepts, e.g. "0.0.0.0:8080" or "localhost:0".
+    // Call it again to listen on several addresses, the default is 127.0.0.1:7878.
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.addrs.push(addr.into());
+        self
+    }
+
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    // Shared by every connection, handlers get it back with `Request::state::<T>()`
+    pub fn state<T: Send + Sync + 'static>(mut self, state: T) -> Self {
+        self.state = Some(Arc::new(state));
+        self
+    }
+
+    pub fn options(mut self, options: ServerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn header_timeout(mut self, header_timeout: Duration) -> Self {
+        self.options.header_timeout = header_timeout;
+        self
+    }
+
+    pub fn body_timeout(mut self, body_timeout: Duration) -> Self {
+        self.options.body_timeout = body_timeout;
+        self
+    }
+
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Self {
+        self.options.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    pub fn max_requests_per_connection(mut self, max_requests_per_connection: usize) -> Self {
+        self.options.max_requests_per_connection = max_requests_per_connection;
+        self
+    }
+
+    pub fn max_request_line_bytes(mut self, max_request_line_bytes: usize) -> Self {
+        self.options.max_request_line_bytes = max_request_line_bytes;
+        self
+    }
+
+    pub fn max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.options.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    pub fn max_header_count(mut self, max_header_count: usize) -> Self {
+        self.options.max_header_count = max_header_count;
+        self
+    }
+
+    pub fn max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.options.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn max_part_bytes(mut self, max_part_bytes: usize) -> Self {
+        self.options.max_part_bytes = max_part_bytes;
+        self
+    }
+
+    pub fn multipart_memory_bytes(mut self, multipart_memory_bytes: usize) -> Self {
+        self.options.multipart_memory_bytes = multipart_memory_bytes;
+        self
+    }
+
+    pub fn upload_dir(mut self, upload_dir: impl Into<PathBuf>) -> Self {
+        self.options.upload_dir = upload_dir.into();
+        self
+    }
+
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.options.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.options.drain_timeout = drain_timeout;
+        self
+    }
+
+    // `None` sends no `Server` header
+    pub fn server_header(mut self, server_header: Option<String>) -> Self {
+        self.options.server_header = server_header;
+        self
+    }
+
+    // Starts a graceful shutdown once `signal` completes, e.g. `shutdown_signal()`
+    pub fn with_graceful_shutdown(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown_signal = Some(Box::pin(signal));
+        self
+    }
+
+    // Binds every listener and starts accepting connections on the current tokio runtime
+    pub async fn serve(self) -> std::io::Result<ServerHandle> {
+        // An empty buffer reads nothing, every connection would look closed
+        if self.options.read_buffer_size == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "read_buffer_size must be at least 1"));
+        }
+        let addrs = if self.addrs.is_empty() { vec!["127.0.0.1:7878".to_string()] } else { self.addrs };
+        let mut listeners = Vec::new();
+        let mut local_addrs = Vec::new();
+        for addr in &addrs {
+            let listener = TcpListener::bind(addr).await?;
+            local_addrs.push(listener.local_addr()?);
+            listeners.push(listener);
+        }
+        let router = Arc::new(self.router);
+        let options = Arc::new(self.options);
+        let state = self.state;
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let shutdown_tx = Arc::new(shutdown_tx);
+
+        if let Some(signal) = self.shutdown_signal {
+            let shutdown_tx = shutdown_tx.clone();
+            tokio::spawn(async move {
+                signal.await;
+                shutdown_tx.send_replace(true);
+            });
+        }
+
+        let task = tokio::spawn(async move {
+            // One accept loop per listener, all feeding the same queue
+            let (accepted_tx, mut accepted_rx) = mpsc::channel(64);
+            let mut acceptors = JoinSet::new();
+            for listener in listeners {
+                let accepted_tx = accepted_tx.clone();
+                acceptors.spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok(connection) => {
+                                if accepted_tx.send(connection).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Err(e) => log_error!("Failed to accept connection: {}", e),
+                        }
+                    }
+                });
+            }
+            drop(accepted_tx);
+
+            let mut shutdown = shutdown_rx.clone();
+            let mut connections = JoinSet::new();
+            loop {
+                let (mut stream, remote_addr) = tokio::select! {
+                    accepted = accepted_rx.recv() => match accepted {
+                        Some(connection) => connection,
+                        None => break,
+                    },
+                    _ = shutdown.wait_for(|&stop| stop) => break,
+                };
+                log_debug!("Connection from {}", remote_addr);
+                let router = router.clone();
+                let options = options.clone();
+                let state = state.clone();
+                let shutdown = shutdown_rx.clone();
+                connections.spawn(async move {
+                    handle_connection(&mut stream, remote_addr, router, &options, state, shutdown).await;
+                });
+                // Forget connections that already finished
+                while connections.try_join_next().is_some() {}
+            }
+
+            // Closes the listeners
+            acceptors.shutdown().await;
+            log_info!("Shutting down, waiting for {} connections", connections.len());
+            let drained = timeout(options.drain_timeout, async {
+                while connections.join_next().await.is_some() {}
+            }).await;
+            if drained.is_err() {
+                log_warn!("Drain deadline passed, closing {} connections", connections.len());
+                connections.shutdown().await;
+            }
+            log_info!("Server stopped");
+        });
+
+        Ok(ServerHandle { local_addrs, task, shutdown_tx })
+    }
+}
+
+// Resolves on Ctrl-C, or on SIGTERM on unix
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log_error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                log_error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => log_info!("Received Ctrl-C"),
+        _ = terminate => log_info!("Received SIGTERM"),
+    }
+}
+
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    task: JoinHandle<()>,
+    shutdown_tx: Arc<watch::Sender<bool>>,
+}
+
+impl ServerHandle {
+    // The address actually bound, useful when binding to port 0.
+    // With several listeners this is the first one.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    // Every bound address, in the order they were passed to `bind`
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    // Stops accepting connections and lets open ones finish their current
+    // request. `wait` returns once they are all closed or the drain timeout passes.
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    pub async fn wait(self) {
+        if let Err(e) = self.task.await {
+            log_error!("Server task failed: {}", e);
+        }
+    }
+}
+
+async fn handle_connection(stream: &mut TcpStream, remote_addr: SocketAddr, router: Arc<Router>, options: &ServerOptions, state: Option<AppState>, mut shutdown: watch::Receiver<bool>) {
+    let mut served = 0;
+    // Kept across requests, so bytes of a pipelined request read along with the
+    // previous one aren't lost. Requests are answered one at a time, in order.
+    let mut dynamo_buffer = Vec::new();
+    let mut pre_buffer = vec![0; options.read_buffer_size];
+    loop {
+        log_debug!("Waiting for request...");
+        // Idle connections are closed without a response, both when the keep-alive
+        // timeout passes and on shutdown. A request that has started is finished.
+        // A pipelined request already in the buffer is not waited for.
+        if dynamo_buffer.is_empty() {
+            let mut peek_buffer = [0u8; 1];
+            tokio::select! {
+                peeked = stream.peek(&mut peek_buffer) => match peeked {
+                    Ok(0) | Err(_) => {
+                        log_debug!("Client {} closed the connection", remote_addr);
+                        return;
+                    }
+                    Ok(_) => {}
+                },
+                _ = sleep(options.keep_alive_timeout) => {
+                    log_debug!("Closing idle connection from {}", remote_addr);
+                    return;
+                }
+                _ = shutdown.wait_for(|&stop| stop) => {
+                    log_debug!("Closing idle connection for shutdown");
+                    return;
+                }
+            }
+        }
+
+        let mut raw_request = match read_request_head(stream, &mut pre_buffer, &mut dynamo_buffer, options).await {
+            Ok(raw_request) => raw_request,
+            Err(e) => {
+                log_warn!("Error reading request: {}", e);
+                send_error(stream, e, targets_api(&dynamo_buffer), false, options).await;
+                return;
+            }
+        };
+        let is_api = is_api_request(raw_request.head().target);
+        let is_head = raw_request.head().method == "HEAD";
+        log_debug!("Is API: {}", is_api);
+
+        // The body was never read, so the connection can't be reused
+        if let Some(response) = reject_expectation(&raw_request.head(), &router) {
+            log_debug!("Refusing the body of {} {}", raw_request.head().method, raw_request.head().target);
+            send_and_close(stream, response, is_head, options).await;
+            return;
+        }
+
+        if let Err(e) = read_request_body(stream, &mut pre_buffer, &mut dynamo_buffer, &mut raw_request, options).await {
+            log_warn!("Error reading request: {}", e);
+            send_error(stream, e, is_api, is_head, options).await;
+            return;
+        }
+
+        let parsed_request = match parse_request_by_type(is_api, &mut raw_request) {
+            Ok(req) => req,
+            Err(e) => {
+                log_warn!("Error parsing request: {}", e);
+                send_error(stream, e, is_api, is_head, options).await;
+                return;
+            }
+        };
+
+        let request = match extract_request_parts(parsed_request, remote_addr) {
+            Ok(request) => request.with_state(state.clone()),
+            Err(e) => {
+                log_warn!("Error extracting request parts: {}", e);
+                send_error(stream, e, is_api, is_head, options).await;
+                return;
+            }
+        };
+
+        served += 1;
+        let last_allowed = options.max_requests_per_connection != 0 && served >= options.max_requests_per_connection;
+        let keep_alive = wants_keep_alive(&request.version, &request.headers) && !last_allowed;
+        let http_1_0 = request.version == "HTTP/1.0";
+        let mut response = match router.route_request(request).await {
+            Ok(res) => res,
+            Err(e) => {
+                // A client's mistake isn't a server error
+                if e.status().is_server_error() {
+                    log_error!("Error handling request: {}", e);
+                } else {
+                    log_warn!("Refusing request: {}", e);
+                }
+                send_error(stream, e, is_api, is_head, options).await;
+                return;
+            }
+        };
+
+        add_server_headers(&mut response, options);
+        let handler_closes = response.headers.has_token("Connection", "close");
+        // Without chunked coding the end of a stream is where the connection ends
+        let unframed = http_1_0 && matches!(response.body, Body::Stream(_));
+        let close = !keep_alive || handler_closes || unframed || *shutdown.borrow();
+        if close {
+            response.headers.insert("Connection", "close");
+        } else {
+            // HTTP/1.1 keeps the connection by default, HTTP/1.0 needs to be told
+            if http_1_0 {
+                response.headers.insert("Connection", "keep-alive");
+            }
+            let mut keep_alive = format!("timeout={}", options.keep_alive_timeout.as_secs());
+            if options.max_requests_per_connection != 0 {
+                keep_alive.push_str(&format!(", max={}", options.max_requests_per_connection - served));
+            }
+            response.headers.insert("Keep-Alive", keep_alive);
+        }
+
+        if let Err(e) = send_response(stream, response, is_head, http_1_0).await {
+            log_warn!("Error sending response: {}", e);
+            return;
+        }
+
+        if close {
+            log_debug!("Close");
+            return;
+        }
+    }
+}
+
+// Answers a request that failed before or inside its handler. The connection is
+// closed after it, since what is left on it can't be trusted, and a client that
+// is already gone isn't answered at all. A HEAD request gets the head of the error only.
+async fn send_error(stream: &mut TcpStream, error: ParseError, is_api: bool, is_head: bool, options: &ServerOptions) {
+    if error.is_disconnect() {
+        return;
+    }
+    send_and_close(stream, error_handler(error, is_api), is_head, options).await;
+}
+
+// Sends the last response of a connection whose request may not have been read in full
+async fn send_and_close(stream: &mut TcpStream, mut response: Response, is_head: bool, options: &ServerOptions) {
+    add_server_headers(&mut response, options);
+    response.headers.insert("Connection", "close");
+    // Error answers always have their body in memory, so they never need chunking
+    if let Err(e) = send_response(stream, response, is_head, false).await {
+        log_debug!("Failed to send response before closing: {}", e);
+        return;
+    }
+
+    // The client may still be sending, e.g. a body that was refused. Closing with
+    // that unread would reset the connection and could lose the response, so our
+    // side is shut first and the rest is read and dropped for a moment (RFC 9112 section 9.6).
+    let _ = stream.shutdown().await;
+    let _ = timeout(LINGER_TIMEOUT, async {
+        let mut discard = [0u8; 1024];
+        while let Ok(n) = stream.read(&mut discard).await && n > 0 {}
+    }).await;
+}
+
+// `Date` (RFC 9110 section 6.6.1) and `Server` on every response, unless the handler set its own
+fn add_server_headers(response: &mut Response, options: &ServerOptions) {
+    if !response.headers.contains("Date") {
+        response.headers.insert("Date", http_date(SystemTime::now()));
+    }
+    if let Some(server) = &options.server_header
+        && !response.headers.contains("Server") {
+        response.headers.insert("Server", server.as_str());
+    }
+}
@@ -0,0 +1,121 @@
+use http_serverrrrr::config::{Config, ConfigError};
+use http_serverrrrr::log::LogLevel;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+    let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    Config::load(args.iter().map(|arg| arg.to_string()), |name| env.get(name).cloned())
+}
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("http_serverrrrr_{}_{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn test_defaults() {
+    let config = load(&[], &[]).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.bind, vec!["127.0.0.1:7878"]);
+    assert_eq!(config.log_level, LogLevel::Info);
+    assert!(config.server_options().server_header.unwrap().starts_with("http_serverrrrr/"));
+
+    // An empty Server header means none at all
+    let config = load(&["--server-header", "edge/1"], &[]).unwrap();
+    assert_eq!(config.server_options().server_header.as_deref(), Some("edge/1"));
+    let config = load(&[], &[("HTTP_SERVER_SERVER_HEADER", "")]).unwrap();
+    assert_eq!(config.server_options().server_header, None);
+}
+
+#[test]
+fn test_precedence_file_env_flags() {
+    let path = write_config(
+        "precedence",
+        r#"
+bind = ["127.0.0.1:8000", "127.0.0.1:8001"]
+header_timeout = 3
+body_timeout = 4
+max_body_bytes = 100
+log_level = "debug"
+"#,
+    );
+    let path = path.to_str().unwrap();
+
+    let config = load(
+        &["--config", path, "--body-timeout", "6", "--max-body-bytes=300"],
+        &[("HTTP_SERVER_BODY_TIMEOUT", "5"), ("HTTP_SERVER_MAX_BODY_BYTES", "200"), ("HTTP_SERVER_LOG_LEVEL", "warn")],
+    )
+    .unwrap();
+
+    assert_eq!(config.bind, vec!["127.0.0.1:8000", "127.0.0.1:8001"]);
+    assert_eq!(config.header_timeout, Duration::from_secs(3));
+    assert_eq!(config.body_timeout, Duration::from_secs(6));
+    assert_eq!(config.max_body_bytes, 300);
+    assert_eq!(config.log_level, LogLevel::Warn);
+    assert_eq!(config.server_options().body_timeout, Duration::from_secs(6));
+
+    // Read buffer, drain timeout and upload threshold reach the server too
+    let config = load(
+        &["--config", path, "--read-buffer-size", "4096"],
+        &[("HTTP_SERVER_DRAIN_TIMEOUT", "7"), ("HTTP_SERVER_MULTIPART_MEMORY_BYTES", "0")],
+    )
+    .unwrap();
+    let options = config.server_options();
+    assert_eq!(options.read_buffer_size, 4096);
+    assert_eq!(options.drain_timeout, Duration::from_secs(7));
+    assert_eq!(options.multipart_memory_bytes, 0);
+
+    // The config file can also come from the environment, repeated --bind flags add up
+    let config = load(&["-b", "0.0.0.0:1", "--bind", "0.0.0.0:2"], &[("HTTP_SERVER_CONFIG", path)]).unwrap();
+    assert_eq!(config.bind, vec!["0.0.0.0:1", "0.0.0.0:2"]);
+    assert_eq!(config.header_timeout, Duration::from_secs(3));
+}
+
+#[test]
+fn test_invalid_values_are_reported() {
+    let err = load(&["--header-timeout", "0"], &[]).unwrap_err();
+    assert_eq!(err.to_string(), "invalid value \"0\" for --header-timeout: must be at least 1 second");
+
+    let err = load(&[], &[("HTTP_SERVER_BIND", "localhost")]).unwrap_err();
+    assert!(err.to_string().contains("HTTP_SERVER_BIND"), "{}", err);
+
+    let err = load(&["--log-level", "loud"], &[]).unwrap_err();
+    assert!(err.to_string().contains("expected one of error, warn, info, debug"), "{}", err);
+
+    let err = load(&["--read-buffer-size", "0"], &[]).unwrap_err();
+    assert_eq!(err.to_string(), "invalid value \"0\" for read_buffer_size: must be at least 1");
+
+    let err = load(&["--drain-timeout", "soon"], &[]).unwrap_err();
+    assert!(err.to_string().contains("expected a whole number of seconds"), "{}", err);
+
+    let err = load(&["--static-root", "does/not/exist"], &[]).unwrap_err();
+    assert!(err.to_string().contains("not a directory"), "{}", err);
+
+    let err = load(&[], &[("HTTP_SERVER_UPLOAD_DIR", "does/not/exist")]).unwrap_err();
+    assert!(err.to_string().contains("upload_dir"), "{}", err);
+
+    assert!(matches!(load(&["--frobnicate"], &[]), Err(ConfigError::Usage(_))));
+    assert!(matches!(load(&["--bind"], &[]), Err(ConfigError::Usage(_))));
+    assert_eq!(load(&["--help"], &[]), Err(ConfigError::Help));
+}
+
+#[test]
+fn test_invalid_config_files() {
+    let path = write_config("unknown_key", "max_body_bytez = 1\n");
+    let err = load(&["--config", path.to_str().unwrap()], &[]).unwrap_err();
+    assert!(matches!(err, ConfigError::Parse { .. }), "{:?}", err);
+    assert!(err.to_string().contains("unknown key `max_body_bytez`"), "{}", err);
+
+    let path = write_config("bad_value", "max_body_bytes = -5\n");
+    let err = load(&["--config", path.to_str().unwrap()], &[]).unwrap_err();
+    assert!(err.to_string().contains("max_body_bytes"), "{}", err);
+
+    let path = write_config("bad_syntax", "bind = [\n");
+    assert!(matches!(load(&["--config", path.to_str().unwrap()], &[]), Err(ConfigError::Parse { .. })));
+
+    let err = load(&["--config", "missing.toml"], &[]).unwrap_err();
+    assert!(matches!(err, ConfigError::Read { .. }), "{:?}", err);
+}
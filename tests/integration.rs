@@ -1,14 +1,34 @@
+use http_serverrrrr::Server;
+use http_serverrrrr::routes::app_router;
 use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, OnceLock};
+
+// One server on an ephemeral port, shared by every test in this file
+fn server_addr() -> SocketAddr {
+    static ADDR: OnceLock<SocketAddr> = OnceLock::new();
+    *ADDR.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async {
+                let server = Server::builder().bind("127.0.0.1:0").router(app_router().unwrap()).serve().await.unwrap();
+                tx.send(server.local_addr()).unwrap();
+                server.wait().await;
+            });
+        });
+        rx.recv().unwrap()
+    })
+}
 
 #[test]
 fn test_server_error_handling() {
     // Test malformed request
-    let mut stream = TcpStream::connect("127.0.0.1:7878").unwrap();
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
     stream.write_all(b"GARBAGE DATA\r\n").unwrap();
     
     let mut buffer = [0; 512];
-    stream.read(&mut buffer).unwrap();
+    let _ = stream.read(&mut buffer).unwrap();
     
     // Verify 400 response
     assert!(
@@ -17,44 +37,982 @@ fn test_server_error_handling() {
 }
 
 fn send_test_request(path: &str, method: &str) -> String {
-    let mut stream = TcpStream::connect("127.0.0.1:7878").unwrap();
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
     let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
     stream.write_all(request.as_bytes()).unwrap();
     
     let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
+    let _ = stream.read(&mut buffer).unwrap();
     String::from_utf8_lossy(&buffer).into_owned()
 }
 
 #[test]
 fn test_routes_integration() {
-    // Start your server in a separate thread if not already running
-    // std::thread::spawn(|| your_server::main());
-
     let test_cases = [
         ("GET", "/", "HOME", "200 OK"),
         ("GET", "/about", "ABOUT", "200 OK"),
         ("GET", "/submit", "SUBMIT GET", "200 OK"),
         ("POST", "/submit", "SUBMIT POST", "200 OK"),
-        ("GET", "/nonexistent", "NOT FOUND", "404 NOT FOUND"),
+        ("GET", "/nonexistent", "was not found", "404 Not Found"),
     ];
 
     for (method, path, content, status) in test_cases {
         let response = send_test_request(path, method);
         assert!(response.contains(content), "Failed for {} {}", method, path);
         assert!(response.starts_with(&format!("HTTP/1.1 {}", status)));
+        assert!(!response.contains("\r\nStatus:"), "{}", response);
     }
 }
 
 #[test]
 fn test_error_conditions() {
     let test_cases = [
-        ("GET", "/../etc/passwd", "403 FORBIDDEN"),
-        ("GARBAGE", "/", "400 BAD REQUEST"),
+        ("GET", "/../etc/passwd", "403 Forbidden"),
+        ("GARBAGE", "/", "400 Bad Request"),
     ];
 
     for (method, path, status) in test_cases {
         let response = send_test_request(path, method);
         assert!(response.starts_with(&format!("HTTP/1.1 {}", status)));
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_chunked_request_body() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    let request = "POST /api/v1/posts HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+                   7;ext=1\r\n{\"name\"\r\n\
+                   A\r\n: \"chunky\"\r\n\
+                   1\r\n}\r\n\
+                   0\r\nX-Trailer: done\r\n\r\n";
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("chunky"), "{}", response);
+}
+
+#[test]
+fn test_chunked_response_body() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(b"GET /chunky HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("Transfer-Encoding: chunked"), "{}", response);
+    assert!(response.contains("<p>CHUNK 5</p>"), "{}", response);
+    assert!(response.ends_with("0\r\n\r\n"), "{}", response);
+
+    // HTTP/1.0 has no chunked coding, the body is sent as is and ends with the connection
+    for request in ["GET /chunky HTTP/1.0\r\n\r\n", "GET /chunky HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"] {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(!response.contains("Transfer-Encoding"), "{}", response);
+        assert!(response.contains("Connection: close\r\n"), "{}", response);
+        assert!(!response.contains("Keep-Alive"), "{}", response);
+        assert!(response.ends_with("<p>CHUNK 5</p></body></html>"), "{}", response);
+    }
+}
+
+#[test]
+fn test_case_insensitive_headers() {
+    let body = "{\"name\": \"lowercase\"}";
+    let request = format!(
+        "POST /api/v1/posts HTTP/1.1\r\nhost: localhost\r\ncontent-type:application/json\r\nACCEPT: text/html\r\naccept: application/json\r\ncontent-length: {}\r\nconnection:\tclose\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("lowercase"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_server_builder_binds_ephemeral_port() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::builder().bind("127.0.0.1:0").router(app_router().unwrap()).serve().await.unwrap();
+    let addr = server.local_addr();
+    assert_ne!(addr.port(), 0);
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /about HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("ABOUT"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_read_buffer_size() {
+    use http_serverrrrr::ServerOptions;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let err = Server::builder().bind("127.0.0.1:0").read_buffer_size(0).serve().await.err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    let options = ServerOptions { read_buffer_size: 0, ..ServerOptions::default() };
+    assert!(Server::builder().bind("127.0.0.1:0").options(options).serve().await.is_err());
+
+    // A tiny buffer only means more reads
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .read_buffer_size(3)
+        .serve()
+        .await
+        .unwrap();
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(b"GET /about HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_method_not_allowed_and_options() {
+    let response = send_test_request("/about", "DELETE");
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"), "{}", response);
+    assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"), "{}", response);
+
+    let response = send_test_request("/api/v1/posts", "PUT");
+    assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+    assert!(response.contains("Allow: GET, HEAD, POST, OPTIONS\r\n"), "{}", response);
+
+    let response = send_test_request("/submit", "OPTIONS");
+    assert!(response.starts_with("HTTP/1.1 204 No Content"), "{}", response);
+    assert!(response.contains("Allow: GET, HEAD, POST, OPTIONS\r\n"), "{}", response);
+    assert!(!response.contains("Content-Length"), "{}", response);
+}
+
+#[test]
+fn test_head_mirrors_get_without_body() {
+    let request = |method: &str, path: &str| {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(format!("{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", method, path).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    // The head without `Date`, which can tick between the two requests
+    let head_of = |response: &str| {
+        let head = &response[..response.find("\r\n\r\n").unwrap() + 4];
+        head.split_inclusive("\r\n").filter(|line| !line.starts_with("Date: ")).collect::<String>()
+    };
+
+    for path in ["/", "/style.css", "/api/v1/users?name=ann", "/missing"] {
+        let get = request("GET", path);
+        let head = request("HEAD", path);
+        assert_eq!(head_of(&head), head_of(&get), "{}", path);
+        assert!(head.ends_with("\r\n\r\n"), "{}", head);
+        assert!(head.contains("Content-Length: "), "{}", head);
+    }
+
+    // A streamed body keeps its `Transfer-Encoding` but sends no chunks
+    let head = request("HEAD", "/chunky");
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(head.ends_with("\r\n\r\n"), "{}", head);
+}
+
+#[tokio::test]
+async fn test_response_builder_on_the_wire() {
+    use http_serverrrrr::http_utils::cookie::{Cookie, SameSite};
+    use http_serverrrrr::http_utils::request::handler::sync;
+    use http_serverrrrr::http_utils::request::router::Router;
+    use http_serverrrrr::http_utils::status::Status;
+    use http_serverrrrr::http_utils::types::Response;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let file = std::env::temp_dir().join(format!("http_serverrrrr_builder_{}.css", std::process::id()));
+    std::fs::write(&file, "body { color: red }").unwrap();
+    let served = file.clone();
+    let router = Router::new()
+        .post("/login", sync(|_| {
+            Ok(Response::new(Status::Created)
+                .header("X-Zebra", "1")
+                .header("Location", "/me")
+                .header("X-Alpha", "2")
+                .cookie(Cookie::new("session", "abc").path("/").http_only(true).same_site(SameSite::Lax))
+                .cookie(Cookie::new("theme", "dark").max_age(Duration::from_secs(60)))
+                .json(&serde_json::json!({ "user": "ferris" })))
+        }))
+        .unwrap()
+        .get("/file", sync(move |_| Ok(Response::ok().file(&served).unwrap())))
+        .unwrap()
+        .get("/stream", sync(|_| Ok(Response::ok().text("ignored").stream(Box::pin(tokio_stream::iter(vec![b"ab".to_vec(), b"cd".to_vec()]))))))
+        .unwrap();
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(router)
+        .server_header(Some("test-server".to_string()))
+        .serve()
+        .await
+        .unwrap();
+
+    let addr = server.local_addr();
+    let request = |request: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+
+    // Headers go out in the order they were set, before the ones the connection adds
+    let response = request("POST /login HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 201 Created\r\nX-Zebra: 1\r\nLocation: /me\r\nX-Alpha: 2\r\n"), "{}", response);
+    assert!(response.contains("Set-Cookie: session=abc; Path=/; HttpOnly; SameSite=Lax\r\n"), "{}", response);
+    assert!(response.contains("Set-Cookie: theme=dark; Max-Age=60\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: application/json\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 17\r\n"), "{}", response);
+    assert!(response.contains("Server: test-server\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n{\"user\":\"ferris\"}"), "{}", response);
+    let date = response.lines().find_map(|line| line.strip_prefix("Date: ")).unwrap();
+    assert!(date.ends_with(" GMT") && date.len() == "Sun, 06 Nov 1994 08:49:37 GMT".len(), "{}", date);
+
+    let response = request("GET /file HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(response.contains("Content-Type: text/css\r\n"), "{}", response);
+    assert!(response.contains("Content-Length: 19\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nbody { color: red }"), "{}", response);
+
+    let response = request("GET /stream HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+    assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"), "{}", response);
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{}", response);
+    assert!(!response.contains("Content-Length"), "{}", response);
+    assert!(response.ends_with("\r\n\r\n2\r\nab\r\n2\r\ncd\r\n0\r\n\r\n"), "{}", response);
+
+    // The bundled server names itself, unless told not to
+    let response = send_test_request("/", "GET");
+    assert!(response.contains("Server: http_serverrrrr/"), "{}", response);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn test_async_handler_with_shared_state() {
+    use http_serverrrrr::http_utils::request::handler::{HandlerResult, Request};
+    use http_serverrrrr::http_utils::request::router::Router;
+    use http_serverrrrr::http_utils::response::api_response;
+    use http_serverrrrr::http_utils::status::Status;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    struct UserStore {
+        names: Mutex<Vec<String>>,
+    }
+
+    async fn add_user(req: Request) -> HandlerResult {
+        tokio::task::yield_now().await;
+        let store = req.state::<UserStore>().unwrap();
+        let mut names = store.names.lock().unwrap();
+        names.push(req.params["name"].clone());
+        let body = serde_json::json!({
+            "count": names.len(),
+            "local_client": req.remote_addr.ip().is_loopback(),
+        });
+        Ok(api_response(Status::Created, &body))
+    }
+
+    let router = Router::new().post("/users/:name", add_user).unwrap();
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(router)
+        .state(UserStore { names: Mutex::new(vec!["existing".to_string()]) })
+        .serve()
+        .await
+        .unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(b"POST /users/ferris HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+    assert!(response.contains("\"count\":2"), "{}", response);
+    assert!(response.contains("\"local_client\":true"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown_drains_connections() {
+    use http_serverrrrr::http_utils::request::handler::{sync, HandlerResult, Request};
+    use http_serverrrrr::http_utils::request::router::Router;
+    use http_serverrrrr::http_utils::response::html_response;
+    use http_serverrrrr::http_utils::status::Status;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{sleep, timeout, Duration};
+
+    async fn slow(_req: Request) -> HandlerResult {
+        sleep(Duration::from_millis(300)).await;
+        Ok(html_response(Status::Ok, "SLOW", "SLOW"))
+    }
+
+    let router = Router::new()
+        .get("/slow", slow)
+        .unwrap()
+        .get("/fast", sync(|_| Ok(html_response(Status::Ok, "FAST", "FAST"))))
+        .unwrap();
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(router)
+        .drain_timeout(Duration::from_secs(5))
+        .serve()
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+
+    // An idle keep-alive connection
+    let mut idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+    idle.write_all(b"GET /fast HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut buffer = [0; 1024];
+    let n = idle.read(&mut buffer).await.unwrap();
+    assert!(String::from_utf8_lossy(&buffer[..n]).contains("FAST"));
+
+    // A request still being handled when shutdown starts
+    let mut busy = tokio::net::TcpStream::connect(addr).await.unwrap();
+    busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    server.shutdown();
+
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(2), idle.read_to_end(&mut rest)).await.unwrap().unwrap();
+    assert!(rest.is_empty());
+
+    let mut response = String::new();
+    timeout(Duration::from_secs(2), busy.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(response.contains("SLOW"), "{}", response);
+
+    timeout(Duration::from_secs(2), server.wait()).await.unwrap();
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_multiple_listeners_and_body_limit() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .max_body_bytes(16)
+        .serve()
+        .await
+        .unwrap();
+    assert_eq!(server.local_addrs().len(), 2);
+    assert_eq!(server.local_addr(), server.local_addrs()[0]);
+
+    for addr in server.local_addrs() {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /about HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(b"POST /submit HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_request_size_limits() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .max_request_line_bytes(64)
+        .max_header_bytes(256)
+        .max_header_count(4)
+        .max_body_bytes(8)
+        .serve()
+        .await
+        .unwrap();
+
+    let long_path = "a".repeat(100);
+    let many_headers = "X-Header: 1\r\n".repeat(5);
+    let huge_header = format!("X-Big: {}\r\n", "b".repeat(300));
+    let cases = [
+        (format!("GET /{} HTTP/1.1\r\n\r\n", long_path), "414"),
+        // Refused before the request line even ends
+        (format!("GET /{}", long_path), "414"),
+        (format!("GET / HTTP/1.1\r\n{}\r\n", many_headers), "431"),
+        (format!("GET / HTTP/1.1\r\n{}", huge_header), "431"),
+        ("POST /submit HTTP/1.1\r\nContent-Length: 9\r\n\r\n".to_string(), "413"),
+        ("POST /submit HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n".to_string(), "413"),
+        ("POST /submit HTTP/1.1\r\nContent-Length: 8\r\nConnection: close\r\n\r\n12345678".to_string(), "200"),
+    ];
+
+    for (request, status) in cases {
+        let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}\n{}", request, response);
+    }
+
+    // The default line and head limits are equal, a long line still gets 414
+    let server = Server::builder().bind("127.0.0.1:0").router(app_router().unwrap()).serve().await.unwrap();
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000)).as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 414"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_keep_alive_and_connection_limits() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{timeout, Duration, Instant};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .keep_alive_timeout(Duration::from_millis(300))
+        .max_requests_per_connection(2)
+        .serve()
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+    let mut buffer = [0; 4096];
+
+    // HTTP/1.1 stays open, and the second request is the last one allowed
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /about HTTP/1.1\r\n\r\n").await.unwrap();
+    let n = stream.read(&mut buffer).await.unwrap();
+    let response = String::from_utf8_lossy(&buffer[..n]).into_owned();
+    assert!(response.contains("Keep-Alive: timeout="), "{}", response);
+    assert!(response.contains(", max=1\r\n"), "{}", response);
+    assert!(!response.contains("Connection: close"), "{}", response);
+
+    stream.write_all(b"GET /about HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert!(!response.contains("Keep-Alive"), "{}", response);
+
+    // HTTP/1.0 closes unless asked not to
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /about HTTP/1.0\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /about HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await.unwrap();
+    let n = stream.read(&mut buffer).await.unwrap();
+    let response = String::from_utf8_lossy(&buffer[..n]).into_owned();
+    assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+
+    // Once idle for the keep-alive timeout, it is closed without a 408
+    let idle_since = Instant::now();
+    let mut rest = Vec::new();
+    timeout(Duration::from_secs(2), stream.read_to_end(&mut rest)).await.unwrap().unwrap();
+    assert!(rest.is_empty(), "{}", String::from_utf8_lossy(&rest));
+    assert!(idle_since.elapsed() >= Duration::from_millis(250));
+}
+
+#[test]
+fn test_pipelined_requests() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    // Four requests in one write, the last one closing the connection
+    let requests = concat!(
+        "GET /about HTTP/1.1\r\n\r\n",
+        "POST /submit HTTP/1.1\r\nContent-Length: 5\r\n\r\nfirst",
+        "POST /submit HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nsecond\r\n0\r\n\r\n",
+        "GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    stream.write_all(requests.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let positions: Vec<usize> = ["ABOUT", "first", "second", "HOME"]
+        .iter()
+        .map(|marker| response.find(marker).unwrap_or_else(|| panic!("{} missing from {}", marker, response)))
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{}", response);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(response.matches("Content-Length:").count(), 4, "{}", response);
+}
+
+#[tokio::test]
+async fn test_expect_continue() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{timeout, Duration};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .max_body_bytes(64)
+        .serve()
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+
+    // Told to go on before sending the body
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"POST /submit HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut buffer = [0; 1024];
+    let n = timeout(Duration::from_secs(2), stream.read(&mut buffer)).await.unwrap().unwrap();
+    assert_eq!(&buffer[..n], b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("hello"), "{}", response);
+
+    // Same for a chunked body
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"POST /submit HTTP/1.1\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n").await.unwrap();
+    let n = timeout(Duration::from_secs(2), stream.read(&mut buffer)).await.unwrap().unwrap();
+    assert_eq!(&buffer[..n], b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"5\r\nhello\r\n0\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    // Refused without a 100 and without waiting for the body
+    let cases: [(&[u8], &str); 3] = [
+        (b"POST /submit HTTP/1.1\r\nContent-Length: 5\r\nExpect: something-else\r\n\r\n", "417"),
+        (b"POST /submit HTTP/1.1\r\nContent-Length: 65\r\nExpect: 100-continue\r\n\r\n", "413"),
+        (b"PUT /about HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n", "405"),
+    ];
+    for (request, status) in cases {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut response = String::new();
+        timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+        assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}", response);
+        assert!(!response.contains("100 Continue"), "{}", response);
+    }
+
+    // A client that sends the body anyway still gets the refusal, not a reset
+    let body = "x".repeat(256 * 1024);
+    let request = format!("PUT /about HTTP/1.1\r\nContent-Length: {}\r\nExpect: 100-continue\r\n\r\n{}", body.len(), body);
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+    let sending = tokio::spawn(async move { writer.write_all(request.as_bytes()).await });
+    let mut response = String::new();
+    timeout(Duration::from_secs(2), reader.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+    let _ = sending.await;
+
+    // A body sent along with the head doesn't get a 100
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"POST /submit HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\nhello").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_binary_body_is_not_decoded() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    let body = [0xff, 0xfe, 0x00, 0x80, 0x0a];
+    let mut request = format!(
+        "POST /submit/binary HTTP/1.1\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    stream.write_all(&request).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("BINARY"), "{}", response);
+}
+
+#[test]
+fn test_submitted_bodies_are_escaped() {
+    let cases = [
+        ("text/plain", "<script>alert(1)</script>", "&lt;script&gt;alert(1)&lt;/script&gt;"),
+        ("application/json", r#"{"x":"<img src=x>"}"#, "{&quot;x&quot;:&quot;&lt;img src=x&gt;&quot;}"),
+    ];
+    for (content_type, body, expected) in cases {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        let request = format!(
+            "POST /submit HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains(expected), "{}", response);
+        assert!(!response.contains(body), "{}", response);
+    }
+}
+
+#[test]
+fn test_smuggling_attempts_get_400() {
+    let requests = [
+        "POST /submit HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        "POST /submit HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 40\r\n\r\nbody",
+        "POST /submit HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n",
+    ];
+    for request in requests {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        // Answered, then closed, so nothing is left to be read as a second request
+        assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        assert_eq!(response.matches("Content-Length:").count(), 1, "{}", response);
+    }
+}
+
+#[test]
+fn test_errors_get_precise_statuses() {
+    let cases = [
+        ("GET / HTTP/2.0\r\n\r\n", "505 HTTP Version Not Supported"),
+        ("GET / HTTX/1.1\r\n\r\n", "400 Bad Request"),
+        ("GET / HTTP/1.1\r\nNo colon\r\n\r\n", "400 Bad Request"),
+    ];
+    for (request, status) in cases {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}", response);
+        assert!(response.contains("Content-Type: text/html"), "{}", response);
+    }
+
+    // Whatever came from the client is escaped in the page
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n<script>\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.contains("&lt;script&gt;"), "{}", response);
+}
+
+#[test]
+fn test_api_errors_are_json() {
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(b"POST /api/v1/posts HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 5\r\n\r\n{nope").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+    assert!(response.contains("Content-Type: application/json"), "{}", response);
+
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["body"]["error"]["code"], 400);
+    assert_eq!(body["body"]["error"]["reason"], "Bad Request");
+    assert!(body["body"]["error"]["message"].as_str().unwrap().contains("invalid JSON body"), "{}", body);
+
+    // Even when the head itself is refused
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(b"GET /api/v1/users HTTP/3.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 505"), "{}", response);
+    assert!(response.contains("\"code\":505"), "{}", response);
+
+    // And when no API route answers
+    let cases = [
+        ("DELETE /api/v1/users", "405", "\"code\":405"),
+        ("GET /api/v1/.env", "403", "\"code\":403"),
+        ("GET /api/v1/nope", "404", "\"code\":404"),
+    ];
+    for (request, status, code) in cases {
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(format!("{} HTTP/1.1\r\nConnection: close\r\n\r\n", request).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}\n{}", request, response);
+        assert!(response.contains("Content-Type: application/json"), "{}", response);
+        assert!(response.contains(code), "{}", response);
+    }
+}
+
+#[tokio::test]
+async fn test_slow_request_times_out() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .header_timeout(std::time::Duration::from_millis(200))
+        .serve()
+        .await
+        .unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: loc").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "{}", response);
+    assert!(response.contains("Connection: close"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_percent_encoded_paths_and_queries() {
+    use http_serverrrrr::http_utils::request::handler::{HandlerResult, Request};
+    use http_serverrrrr::http_utils::response::html_response;
+    use http_serverrrrr::http_utils::status::Status;
+    use http_serverrrrr::routes::app_router_with_root;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let root = std::env::temp_dir().join(format!("http_serverrrrr_static_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("images")).unwrap();
+    std::fs::write(root.join("images/my pic.txt"), "a picture").unwrap();
+
+    async fn tags(req: Request) -> HandlerResult {
+        let tags: Vec<&str> = req.query_map.get_all("tag").collect();
+        Ok(html_response(Status::Ok, "TAGS", &tags.join("|")))
+    }
+    let router = app_router_with_root(&root).unwrap().get("/tags", tags).unwrap();
+    let server = Server::builder().bind("127.0.0.1:0").router(router).serve().await.unwrap();
+
+    let cases = [
+        ("GET /images/my%20pic.txt HTTP/1.1\r\nConnection: close\r\n\r\n", "200", "a picture"),
+        ("GET /tags?tag=a+b&tag=c%26d HTTP/1.1\r\nConnection: close\r\n\r\n", "200", "a b|c&d"),
+        // Decoded before the path is checked, so encoded dots don't get around it
+        ("GET /%2e%2e/Cargo.toml HTTP/1.1\r\nConnection: close\r\n\r\n", "403", ""),
+        ("GET /images%2F..%2F..%2FCargo.toml HTTP/1.1\r\nConnection: close\r\n\r\n", "403", ""),
+        // An encoded slash stays inside its segment instead of splitting it
+        ("GET /api/v1/users/a%2Fb HTTP/1.1\r\nConnection: close\r\n\r\n", "200", "\"id\":\"a/b\""),
+        ("GET /images/%ff HTTP/1.1\r\nConnection: close\r\n\r\n", "400", ""),
+    ];
+    for (request, status, body) in cases {
+        let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}\n{}", request, response);
+        assert!(response.contains(body), "{}", response);
+    }
+}
+
+#[test]
+fn test_post_body_is_deserialized() {
+    let send = |content_type: &str, body: &str| {
+        let request = format!(
+            "POST /api/v1/posts HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        );
+        let mut stream = TcpStream::connect(server_addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let body = response[response.find("\r\n\r\n").unwrap() + 4..].to_string();
+        (response, serde_json::from_str::<serde_json::Value>(&body).unwrap())
+    };
+
+    // A quote in the name no longer breaks the JSON that comes back
+    let (response, body) = send("application/json", r#"{"name": "say \"hi\""}"#);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(body["body"]["namessssss"], "say \"hi\"");
+
+    let (response, body) = send("application/json", r#"{"title": "no name"}"#);
+    assert!(response.starts_with("HTTP/1.1 422"), "{}", response);
+    assert!(body["body"]["error"]["message"].as_str().unwrap().contains("missing field `name`"), "{}", body);
+
+    let (response, _) = send("text/plain", "name=plain");
+    assert!(response.starts_with("HTTP/1.1 415"), "{}", response);
+
+    // What `fetch` sends, and JSON under another name
+    let (response, body) = send("application/json; charset=UTF-8", r#"{"name": "fetch"}"#);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(body["body"]["namessssss"], "fetch");
+    let (response, body) = send("Application/Merge-Patch+JSON", r#"{"name": "patch"}"#);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(body["body"]["namessssss"], "patch");
+
+    let (response, body) = send("application/xml", "<name>xml</name>");
+    assert!(response.starts_with("HTTP/1.1 415"), "{}", response);
+    assert!(body["body"]["error"]["message"].as_str().unwrap().contains("got application/xml"), "{}", body);
+    let (response, _) = send("application/json; charset=koi8-r", r#"{"name": "koi"}"#);
+    assert!(response.starts_with("HTTP/1.1 415"), "{}", response);
+}
+
+#[test]
+fn test_form_submission_is_rendered() {
+    let body = "name=Ada+Lovelace&lang=en&lang=fr&note=%3Cb%3Ehi%3C%2Fb%3E";
+    let request = format!(
+        "POST /submit/text HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(server_addr()).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("name: Ada Lovelace<br>lang: en<br>lang: fr<br>note: &lt;b&gt;hi&lt;/b&gt;"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_multipart_uploads() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let upload_dir = std::env::temp_dir().join(format!("http_serverrrrr_uploads_{}", std::process::id()));
+    std::fs::create_dir_all(&upload_dir).unwrap();
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .max_part_bytes(4096)
+        .multipart_memory_bytes(256)
+        .upload_dir(&upload_dir)
+        .serve()
+        .await
+        .unwrap();
+
+    let file = "x".repeat(3000);
+    let body = format!(
+        "--b0und\r\nContent-Disposition: form-data; name=\"who\"\r\n\r\n<ann>\r\n\
+         --b0und\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"notes.txt\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n\
+         --b0und--\r\n",
+        file
+    );
+    let chunked: String = body.as_bytes().chunks(500).map(|chunk| format!("{:X}\r\n{}\r\n", chunk.len(), String::from_utf8_lossy(chunk))).collect();
+    let requests = [
+        format!("POST /submit HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b0und\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body),
+        format!("POST /submit HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b0und\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{}0\r\n\r\n", chunked),
+    ];
+    for request in requests {
+        let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("who: &lt;ann&gt;<br>doc: notes.txt (3000 bytes, text/plain)"), "{}", response);
+    }
+    // The upload went through the upload dir and was cleaned up after
+    assert_eq!(std::fs::read_dir(&upload_dir).unwrap().count(), 0);
+
+    // A part over the limit is refused while it is still arriving
+    let big = body.replace(&file, &"x".repeat(5000));
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    let request = format!("POST /submit HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b0und\r\nContent-Length: {}\r\n\r\n{}", big.len(), big);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    assert_eq!(std::fs::read_dir(&upload_dir).unwrap().count(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_upload_that_cannot_be_stored() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = Server::builder()
+        .bind("127.0.0.1:0")
+        .router(app_router().unwrap())
+        .multipart_memory_bytes(4)
+        .upload_dir("/nonexistent/dir")
+        .serve()
+        .await
+        .unwrap();
+
+    let body = "--b0und\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"notes.txt\"\r\n\r\nmore than four bytes\r\n--b0und--\r\n";
+    let request = format!("POST /submit HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b0und\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    // Our disk failing is answered, not mistaken for the client going away
+    assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_trailers_are_kept_apart_from_headers() {
+    use http_serverrrrr::http_utils::request::handler::{sync, Request};
+    use http_serverrrrr::http_utils::request::router::Router;
+    use http_serverrrrr::http_utils::types::Response;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let router = Router::new()
+        .post(
+            "/echo",
+            sync(|req: Request| {
+                let header = req.headers.get("X-Checksum").unwrap_or("-");
+                let trailer = req.trailers.get("X-Checksum").unwrap_or("-");
+                Ok(Response::ok().text(format!("header={} trailer={}", header, trailer)))
+            }),
+        )
+        .unwrap();
+    let server = Server::builder().bind("127.0.0.1:0").router(router).serve().await.unwrap();
+
+    let request = "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n2\r\nhi\r\n0\r\nX-Checksum: abc\r\n\r\n";
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.ends_with("header=- trailer=abc"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_middleware_refuses_head_before_body() {
+    use http_serverrrrr::http_utils::middleware::{Middleware, Next};
+    use http_serverrrrr::http_utils::parser::RequestHead;
+    use http_serverrrrr::http_utils::request::handler::{sync, BoxFuture, HandlerResult, Request};
+    use http_serverrrrr::http_utils::request::router::Router;
+    use http_serverrrrr::http_utils::status::Status;
+    use http_serverrrrr::http_utils::types::Response;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{timeout, Duration};
+
+    struct RequireToken;
+
+    impl Middleware for RequireToken {
+        fn handle(&self, request: Request, next: Next) -> BoxFuture<HandlerResult> {
+            match request.headers.get("Authorization") {
+                Some("Bearer secret") => next.run(request),
+                _ => Box::pin(async { Ok(Response::new(Status::Unauthorized)) }),
+            }
+        }
+
+        fn check_head(&self, head: &RequestHead) -> Option<Response> {
+            match head.header("Authorization") {
+                Some("Bearer secret") => None,
+                _ => Some(Response::new(Status::Unauthorized).text("no token")),
+            }
+        }
+    }
+
+    let admin = Router::new().post("/upload", sync(|_| Ok(Response::ok().text("stored")))).unwrap().layer(RequireToken);
+    let router = Router::new().nest("/admin", admin).unwrap();
+    let server = Server::builder().bind("127.0.0.1:0").router(router).serve().await.unwrap();
+
+    // Refused from the head alone, the body is never asked for
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream.write_all(b"POST /admin/upload HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    timeout(Duration::from_secs(2), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    assert!(!response.contains("100 Continue"), "{}", response);
+    assert!(response.ends_with("no token"), "{}", response);
+
+    // With the token the client is told to go on
+    let mut stream = tokio::net::TcpStream::connect(server.local_addr()).await.unwrap();
+    stream
+        .write_all(b"POST /admin/upload HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut interim = [0u8; 25];
+    stream.read_exact(&mut interim).await.unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("stored"), "{}", response);
+}
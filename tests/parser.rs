@@ -0,0 +1,411 @@
+use http_serverrrrr::http_utils::media_type::{decode_text, MediaType};
+use http_serverrrrr::http_utils::parser::{deserialize_body, HeadParser, RequestHead};
+use http_serverrrrr::http_utils::query::{percent_decode, QueryMap};
+use http_serverrrrr::http_utils::request::chunked::ChunkedDecoder;
+use http_serverrrrr::http_utils::request::multipart::{multipart_boundary, MultipartLimits, MultipartParser, PartData};
+use http_serverrrrr::http_utils::status::{ParseError, Status};
+use http_serverrrrr::http_utils::types::UniversalBody;
+use serde::Deserialize;
+use std::mem::discriminant;
+use std::path::PathBuf;
+
+fn parse(request: &[u8]) -> Result<Option<RequestHead<'_>>, ParseError> {
+    HeadParser::new().parse(request)
+}
+
+#[test]
+fn test_head_fed_byte_by_byte() {
+    let request = b"POST /submit?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+    let mut parser = HeadParser::new();
+    let head_end = request.len() - 4;
+
+    for end in 0..head_end {
+        assert_eq!(parser.parse(&request[..end]), Ok(None), "complete after {} bytes", end);
+    }
+    let head = parser.parse(&request[..head_end]).unwrap().unwrap();
+    assert_eq!(head.method, "POST");
+    assert_eq!(head.target, "/submit?x=1");
+    assert_eq!(head.version, "HTTP/1.1");
+    assert_eq!(head.headers, vec![("Host", "localhost"), ("Content-Length", "4")]);
+    assert_eq!(head.len, head_end);
+    assert_eq!(head.content_length(), Ok(4));
+
+    // Parsed fields borrow from the buffer instead of copying it
+    let range = request.as_ptr_range();
+    assert!(range.contains(&head.target.as_ptr()));
+
+    // Later calls hand back the same head without rescanning
+    assert_eq!(parser.head(request), Some(head.clone()));
+    assert_eq!(parser.parse(request).unwrap(), Some(head));
+}
+
+#[test]
+fn test_bare_lf_and_leading_empty_lines() {
+    let request = b"\r\n\nGET / HTTP/1.1\nHost: a\r\nAccept:*/*\n\nbody";
+    let head = parse(request).unwrap().unwrap();
+    assert_eq!(head.method, "GET");
+    assert_eq!(head.headers, vec![("Host", "a"), ("Accept", "*/*")]);
+    assert_eq!(&request[head.len..], b"body");
+}
+
+#[test]
+fn test_header_helpers() {
+    let request = b"PUT /x HTTP/1.1\r\nTransfer-Encoding: Chunked\r\nexpect: 100-Continue\r\nX-A: 1\r\nx-a: 2\r\n\r\n";
+    let head = parse(request).unwrap().unwrap();
+    assert!(head.is_chunked());
+    assert!(head.expects_continue());
+    assert_eq!(head.header("X-A"), Some("1"));
+    assert_eq!(head.header_values("X-A").collect::<Vec<_>>(), vec!["1", "2"]);
+    assert_eq!(head.content_length(), Ok(0));
+    assert_eq!(head.header_map().get_all("x-a").collect::<Vec<_>>(), vec!["1", "2"]);
+
+    let head = parse(b"PUT /x HTTP/1.0\r\nExpect: 100-continue\r\n\r\n").unwrap().unwrap();
+    assert!(!head.expects_continue());
+}
+
+#[test]
+fn test_malformed_heads() {
+    let any = String::new;
+    let cases: [(&[u8], ParseError); 8] = [
+        (b"GARBAGE\r\n\r\n", ParseError::BadRequestLine(any())),
+        (b"FETCH / HTTP/1.1\r\n\r\n", ParseError::BadRequestLine(any())),
+        (b"GET / HTTX/1.1\r\n\r\n", ParseError::BadRequestLine(any())),
+        (b"GET / HTTP/2.0\r\n\r\n", ParseError::UnsupportedVersion(any())),
+        (b"GET / HTTP/1.1\r\nNo colon here\r\n\r\n", ParseError::InvalidHeader(any())),
+        (b"GET / HTTP/1.1\r\n: empty name\r\n\r\n", ParseError::InvalidHeader(any())),
+        (b"GET / HTTP/1.1\r\nX-Bad: \xff\xfe\r\n\r\n", ParseError::InvalidHeader(any())),
+        (b"GET / HTTP/1.1\r\nX Odd: 1\r\n\r\n", ParseError::InvalidHeader(any())),
+    ];
+    for (request, expected) in cases {
+        let error = parse(request).unwrap_err();
+        assert_eq!(discriminant(&error), discriminant(&expected), "{}: {:?}", String::from_utf8_lossy(request), error);
+    }
+    // The errors say what was wrong
+    assert_eq!(parse(b"GARBAGE\r\n\r\n"), Err(ParseError::BadRequestLine("GARBAGE".to_string())));
+    assert_eq!(parse(b"GET / HTTP/2.0\r\n\r\n"), Err(ParseError::UnsupportedVersion("HTTP/2.0".to_string())));
+    assert!(parse(b"GET / HTTP/1.1\r\nNo colon here\r\n\r\n").unwrap_err().to_string().contains("No colon here"));
+    // A bad request line is reported as soon as it ends
+    assert!(matches!(parse(b"GARBAGE\r\nHost"), Err(ParseError::BadRequestLine(_))));
+    // Later HTTP/1 minor versions are fine
+    assert!(parse(b"GET / HTTP/1.2\r\n\r\n").unwrap().is_some());
+}
+
+#[test]
+fn test_errors_map_to_statuses() {
+    let cases = [
+        (ParseError::BadRequestLine("GARBAGE".to_string()), Status::BadRequest),
+        (ParseError::InvalidHeader("no colon".to_string()), Status::BadRequest),
+        (ParseError::ConflictingContentLength, Status::BadRequest),
+        (ParseError::UnsupportedVersion("HTTP/2.0".to_string()), Status::HttpVersionNotSupported),
+        (ParseError::UnsupportedMediaType("text/csv".to_string()), Status::UnsupportedMediaType),
+        (ParseError::RequestLineTooLong, Status::UriTooLong),
+        (ParseError::HeadersTooLarge, Status::RequestHeaderFieldsTooLarge),
+        (ParseError::BodyTooLarge(10), Status::ContentTooLarge),
+        (ParseError::Timeout, Status::RequestTimeout),
+        (ParseError::Storage("disk full".to_string()), Status::InternalServerError),
+        (ParseError::Handler("database is down".to_string()), Status::InternalServerError),
+    ];
+    for (error, status) in cases {
+        assert_eq!(error.status(), status, "{}", error);
+    }
+    assert!(ParseError::Io("broken pipe".to_string()).is_disconnect());
+    assert!(!ParseError::Storage("disk full".to_string()).is_disconnect());
+    assert_eq!(ParseError::BodyTooLarge(10).to_string(), "request body is over 10 bytes");
+    assert_eq!(Status::HttpVersionNotSupported.code(), 505);
+    assert_eq!(Status::HttpVersionNotSupported.reason(), "HTTP Version Not Supported");
+}
+
+#[test]
+fn test_status_codes() {
+    assert_eq!(Status::Ok.line(), "HTTP/1.1 200 OK");
+    assert_eq!(Status::NotFound.line(), "HTTP/1.1 404 Not Found");
+    assert_eq!(Status::ContentTooLarge.to_string(), "413 Content Too Large");
+    assert_eq!(Status::ImUsed.reason(), "IM Used");
+
+    // Every registered code maps back to its own variant
+    for code in 100..=999 {
+        let status = Status::from_code(code).unwrap();
+        assert_eq!(status.code(), code);
+        assert_eq!(matches!(status, Status::Custom(_)), status.reason().is_empty(), "{}", code);
+    }
+    assert!(matches!(Status::from_code(418), Some(Status::Custom(418))));
+    assert!(matches!(Status::from_code(451), Some(Status::UnavailableForLegalReasons)));
+    assert_eq!(Status::from_code(99), None);
+    assert_eq!(Status::from_code(1000), None);
+    assert_eq!(Status::Custom(404), Status::NotFound);
+    assert_eq!(Status::Custom(599).line(), "HTTP/1.1 599 ");
+    assert_eq!(Status::Custom(599).to_string(), "599");
+    assert!(Status::from_code(42).is_none() && Status::from_code(1000).is_none());
+    assert_eq!(Status::Custom(42).line(), "HTTP/1.1 500 Internal Server Error");
+    assert_eq!(Status::Custom(1000).line(), "HTTP/1.1 500 Internal Server Error");
+    assert_eq!(serde_json::to_string(&Status::Created).unwrap(), "201");
+
+    assert!(Status::EarlyHints.is_informational());
+    assert!(Status::NoContent.is_success() && !Status::NoContent.is_redirection());
+    assert!(Status::PermanentRedirect.is_redirection());
+    assert!(Status::Custom(499).is_client_error() && !Status::Custom(499).is_server_error());
+    assert!(Status::NetworkAuthenticationRequired.is_server_error());
+}
+
+#[test]
+fn test_head_limits() {
+    let limited = || HeadParser::new().with_limits(20, 64, 2);
+
+    assert_eq!(limited().parse(b"GET /a-rather-long-path"), Err(ParseError::RequestLineTooLong));
+    assert_eq!(limited().parse(b"GET /a-rather-long-path HTTP/1.1\r\n\r\n"), Err(ParseError::RequestLineTooLong));
+    assert_eq!(limited().parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), Err(ParseError::HeadersTooLarge));
+    let big = format!("GET / HTTP/1.1\r\nA: {}", "x".repeat(60));
+    assert_eq!(limited().parse(big.as_bytes()), Err(ParseError::HeadersTooLarge));
+    assert!(limited().parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n").unwrap().is_some());
+
+    // With equal limits a long request line is still a 414, not a 431
+    let equal = || HeadParser::new().with_limits(64, 64, 2);
+    let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+    assert_eq!(equal().parse(long_line.as_bytes()), Err(ParseError::RequestLineTooLong));
+    assert_eq!(equal().parse(&long_line.as_bytes()[..80]), Err(ParseError::RequestLineTooLong));
+}
+
+#[test]
+fn test_ambiguous_framing_is_refused() {
+    let cases: [(&[u8], ParseError); 12] = [
+        (b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n", ParseError::ContentLengthWithTransferEncoding),
+        (b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n", ParseError::ContentLengthWithTransferEncoding),
+        (b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n", ParseError::ConflictingContentLength),
+        (b"POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\n", ParseError::ConflictingContentLength),
+        (b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\n", ParseError::InvalidContentLength),
+        (b"POST / HTTP/1.1\r\nContent-Length: 0x10\r\n\r\n", ParseError::InvalidContentLength),
+        (b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n", ParseError::InvalidContentLength),
+        (b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n", ParseError::UnsupportedTransferEncoding),
+        (b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n", ParseError::UnsupportedTransferEncoding),
+        (b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n", ParseError::UnsupportedTransferEncoding),
+        (b"POST / HTTP/1.1\r\nContent-Length : 5\r\n\r\n", ParseError::WhitespaceBeforeColon),
+        (b"POST / HTTP/1.1\r\nX-Long: a\r\n  b\r\n\r\n", ParseError::ObsoleteLineFolding),
+    ];
+    for (request, error) in cases {
+        assert_eq!(parse(request), Err(error), "{}", String::from_utf8_lossy(request));
+    }
+
+    // Repeats of the same length are fine
+    let head = parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5, 5\r\n\r\n").unwrap().unwrap();
+    assert_eq!(head.content_length(), Ok(5));
+    // So are names made of any token characters
+    assert!(parse(b"GET / HTTP/1.1\r\nX-Odd_Name!#: 1\r\n\r\n").unwrap().is_some());
+}
+
+#[test]
+fn test_percent_decoding() {
+    assert_eq!(percent_decode("/images/my%20pic.jpg", false).unwrap(), "/images/my pic.jpg");
+    assert_eq!(percent_decode("a+b%2Bc", false).unwrap(), "a+b+c");
+    assert_eq!(percent_decode("a+b%2Bc", true).unwrap(), "a b+c");
+    assert_eq!(percent_decode("caf%C3%A9", false).unwrap(), "café");
+    // Stray percent signs are left alone
+    assert_eq!(percent_decode("100%", true).unwrap(), "100%");
+    assert_eq!(percent_decode("%zz%4", true).unwrap(), "%zz%4");
+    assert!(matches!(percent_decode("%ff", false), Err(ParseError::MalformedRequest(_))));
+}
+
+#[test]
+fn test_query_map() {
+    let query = QueryMap::parse("tag=a&tag=b&q=hello+world%21&flag&&empty=").unwrap();
+    assert_eq!(query.get("tag"), Some("a"));
+    assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(query.get("q"), Some("hello world!"));
+    assert_eq!(query.get("flag"), Some(""));
+    assert_eq!(query.get("empty"), Some(""));
+    assert_eq!(query.len(), 5);
+    assert!(!query.contains(""));
+
+    assert!(QueryMap::parse("").unwrap().is_empty());
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Search {
+        q: String,
+        page: u32,
+        limit: Option<u32>,
+    }
+    let search: Search = QueryMap::parse("q=rust%20lang&page=2").unwrap().deserialize().unwrap();
+    assert_eq!(search, Search { q: "rust lang".to_string(), page: 2, limit: None });
+
+    let error = QueryMap::parse("q=x&page=two").unwrap().deserialize::<Search>().unwrap_err();
+    assert!(matches!(error, ParseError::MalformedRequest(_)), "{:?}", error);
+}
+
+#[test]
+fn test_form_bodies_are_decoded() {
+    let body = deserialize_body(b"name=J%C3%BCrgen+M&tag=a&tag=b&empty=", "application/x-www-form-urlencoded").unwrap();
+    let UniversalBody::Form(fields) = body else {
+        panic!("expected a form body, got {:?}", body);
+    };
+    assert_eq!(fields.get("name"), Some("Jürgen M"));
+    assert_eq!(fields.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(fields.get("empty"), Some(""));
+
+    assert!(matches!(deserialize_body(b"a=\xff", "application/x-www-form-urlencoded"), Err(ParseError::MalformedRequest(_))));
+}
+
+#[test]
+fn test_media_types() {
+    let media_type = MediaType::parse("Application/Problem+JSON ; Charset=\"UTF-8\"; title=\"a \\\"b\\\"; c\"").unwrap();
+    assert_eq!(media_type.essence(), "application/problem+json");
+    assert_eq!(media_type.suffix.as_deref(), Some("json"));
+    assert_eq!(media_type.charset(), Some("UTF-8"));
+    assert_eq!(media_type.param("TITLE"), Some("a \"b\"; c"));
+    assert!(media_type.is_json());
+    assert_eq!(media_type.to_string(), "application/problem+json; charset=UTF-8; title=\"a \\\"b\\\"; c\"");
+
+    let pattern = |text: &str| MediaType::parse(text).unwrap();
+    assert!(media_type.matches(&pattern("application/*+json")));
+    assert!(media_type.matches(&pattern("*/*")));
+    assert!(media_type.matches(&pattern("application/*")));
+    assert!(!media_type.matches(&pattern("application/json")));
+    assert!(!pattern("text/plain").is_json());
+
+    for invalid in ["", "json", "text/", "/plain", "text/pla in", "text/plain; charset", "text/plain; a=\"open", "text/plain; a=\"b\"c"] {
+        assert!(matches!(MediaType::parse(invalid), Err(ParseError::InvalidHeader(_))), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn test_charsets_are_decoded() {
+    assert_eq!(decode_text("héllo".as_bytes(), None).unwrap(), "héllo");
+    assert_eq!(decode_text(b"h\xe9llo", Some("ISO-8859-1")).unwrap(), "héllo");
+    assert_eq!(decode_text(b"h\x00i\x00", Some("utf-16le")).unwrap(), "hi");
+    assert_eq!(decode_text(b"\xfe\xff\x00h\x00i", Some("utf-16")).unwrap(), "hi");
+    assert!(matches!(decode_text(b"h\xe9llo", Some("utf-8")), Err(ParseError::MalformedRequest(_))));
+    assert!(matches!(decode_text(b"h\xe9llo", Some("us-ascii")), Err(ParseError::MalformedRequest(_))));
+    assert_eq!(decode_text(b"hi", Some("koi8-r")).unwrap_err().status(), Status::UnsupportedMediaType);
+
+    let body = deserialize_body(b"{\"name\": \"caf\xe9\"}", "application/json; charset=latin1").unwrap();
+    assert!(matches!(&body, UniversalBody::Json(value) if value["name"] == "café"), "{:?}", body);
+    let body = deserialize_body(br#"{"title": "gone"}"#, "application/problem+json").unwrap();
+    assert!(matches!(body, UniversalBody::Json(_)), "{:?}", body);
+    let body = deserialize_body(b"caf\xe9", "text/csv; charset=iso-8859-1").unwrap();
+    assert!(matches!(&body, UniversalBody::Text(text) if text == "café"), "{:?}", body);
+    let body = deserialize_body(b"a=1", "application/x-www-form-urlencoded; charset=UTF-8").unwrap();
+    assert!(matches!(&body, UniversalBody::Form(fields) if fields.get("a") == Some("1")), "{:?}", body);
+    // A type that doesn't parse is passed on like an unknown one
+    let body = deserialize_body(b"\xff", "not a type").unwrap();
+    assert!(matches!(body, UniversalBody::Binary(_)), "{:?}", body);
+}
+
+fn multipart_body() -> Vec<u8> {
+    let mut body = b"preamble to ignore\r\n--XyZ\r\n".to_vec();
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\nworld\r\n--XyZ\r\n");
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"upload\"; filename=\"a;b \\\"c\\\".bin\"\r\nContent-Type: application/octet-stream\r\n\r\n");
+    body.extend((0..=255u8).cycle().take(1000));
+    body.extend_from_slice(b"\r\n--XyZ--\r\nepilogue");
+    body
+}
+
+fn upload_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("http_serverrrrr_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_multipart_fed_byte_by_byte() {
+    assert_eq!(multipart_boundary("multipart/form-data; boundary=\"XyZ\"").unwrap(), Some("XyZ".to_string()));
+    assert_eq!(multipart_boundary("text/plain").unwrap(), None);
+    assert!(multipart_boundary("multipart/form-data").is_err());
+
+    let body = multipart_body();
+    let mut parser = MultipartParser::new("XyZ", MultipartLimits::default());
+    for byte in &body {
+        parser.feed(std::slice::from_ref(byte)).unwrap();
+    }
+    let multipart = parser.finish().unwrap();
+
+    assert_eq!(multipart.parts().len(), 2);
+    assert_eq!(multipart.field("title"), Some("Hello\r\nworld"));
+    let file = multipart.file("upload").unwrap();
+    assert_eq!(file.filename.as_deref(), Some("a;b \"c\".bin"));
+    assert_eq!(file.content_type.as_deref(), Some("application/octet-stream"));
+    assert_eq!(file.bytes().unwrap(), (0..=255u8).cycle().take(1000).collect::<Vec<_>>());
+    assert!(matches!(file.data, PartData::Memory(_)));
+}
+
+#[test]
+fn test_multipart_files_go_to_disk() {
+    let dir = upload_dir("multipart_disk");
+    let limits = MultipartLimits { memory_bytes: 100, upload_dir: dir.clone(), ..MultipartLimits::default() };
+    let mut parser = MultipartParser::new("XyZ", limits);
+    for piece in multipart_body().chunks(7) {
+        parser.feed(piece).unwrap();
+    }
+    let multipart = parser.finish().unwrap();
+
+    // Fields stay in memory whatever their size, files over the threshold don't
+    assert_eq!(multipart.field("title"), Some("Hello\r\nworld"));
+    let file = multipart.file("upload").unwrap();
+    let PartData::File(temp_file) = &file.data else {
+        panic!("expected the upload on disk");
+    };
+    let path = temp_file.path().to_path_buf();
+    assert!(path.starts_with(&dir));
+    assert_eq!(std::fs::read(&path).unwrap().len(), 1000);
+    assert_eq!(file.len(), 1000);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    // Deleted with the last part holding it
+    let copy = multipart.clone();
+    drop(multipart);
+    assert!(path.exists());
+    drop(copy);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_multipart_limits_and_errors() {
+    let limited = |max_part_bytes, max_total_bytes| {
+        let limits = MultipartLimits { max_part_bytes, max_total_bytes, ..MultipartLimits::default() };
+        let mut parser = MultipartParser::new("XyZ", limits);
+        parser.feed(&multipart_body()).and_then(|_| parser.finish())
+    };
+    assert!(limited(1000, 2000).is_ok());
+    assert_eq!(limited(999, 2000).unwrap_err(), ParseError::BodyTooLarge(999));
+    assert_eq!(limited(1000, 1005).unwrap_err(), ParseError::BodyTooLarge(1005));
+
+    let cases: [&[u8]; 3] = [
+        // Never closed
+        b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue",
+        // No name
+        b"--XyZ\r\nContent-Disposition: form-data\r\n\r\nvalue\r\n--XyZ--",
+        // Not a delimiter line
+        b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--XyZjunk\r\n",
+    ];
+    for body in cases {
+        let mut parser = MultipartParser::new("XyZ", MultipartLimits::default());
+        let result = parser.feed(body).and_then(|_| parser.finish());
+        assert!(matches!(result, Err(ParseError::MalformedRequest(_))), "{}: {:?}", String::from_utf8_lossy(body), result);
+    }
+}
+
+#[test]
+fn test_chunked_trailers() {
+    let body = b"5\r\nhello\r\n0\r\nX-Checksum: abc \r\nX-Other:\tdone\r\n\r\nNEXT";
+    let mut decoder = ChunkedDecoder::new().with_limits(100, 2);
+    assert_eq!(decoder.feed(body), Ok(body.len() - 4));
+    assert!(decoder.is_done());
+    let (data, trailers) = decoder.into_parts();
+    assert_eq!(data, b"hello");
+    assert_eq!(trailers, vec![("X-Checksum".to_string(), "abc".to_string()), ("X-Other".to_string(), "done".to_string())]);
+
+    // Held to the same rules and limits as header lines
+    let too_big = format!("0\r\nX-Big: {}\r\n\r\n", "x".repeat(100));
+    let cases: [(&[u8], ParseError); 5] = [
+        (b"0\r\nX-Checksum : abc\r\n\r\n", ParseError::WhitespaceBeforeColon),
+        (b"0\r\nX-Checksum: abc\r\n folded\r\n\r\n", ParseError::ObsoleteLineFolding),
+        (b"0\r\nX Checksum: abc\r\n\r\n", ParseError::InvalidHeader(String::new())),
+        (b"0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", ParseError::HeadersTooLarge),
+        (too_big.as_bytes(), ParseError::HeadersTooLarge),
+    ];
+    for (body, error) in cases {
+        let result = ChunkedDecoder::new().with_limits(100, 2).feed(body);
+        assert_eq!(result.map_err(|e| discriminant(&e)), Err(discriminant(&error)), "{}", String::from_utf8_lossy(body));
+    }
+}
@@ -0,0 +1,92 @@
+use http_serverrrrr::http_utils::cookie::{Cookie, SameSite};
+use http_serverrrrr::http_utils::date::http_date;
+use http_serverrrrr::http_utils::response::{ApiError, IntoResponse};
+use http_serverrrrr::http_utils::status::{ParseError, Status};
+use http_serverrrrr::http_utils::types::{Body, Response};
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn test_http_dates() {
+    let at = |secs: u64| http_date(UNIX_EPOCH + Duration::from_secs(secs));
+    assert_eq!(at(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(at(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+    // Leap day, and the last second of a leap year
+    assert_eq!(at(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    assert_eq!(at(1_735_689_599), "Tue, 31 Dec 2024 23:59:59 GMT");
+    assert_eq!(http_date(UNIX_EPOCH - Duration::from_secs(1)), "Thu, 01 Jan 1970 00:00:00 GMT");
+}
+
+#[test]
+fn test_cookies() {
+    assert_eq!(Cookie::new("id", "42").to_string(), "id=42");
+    let cookie = Cookie::new("session", "abc")
+        .path("/app")
+        .domain("example.com")
+        .max_age(Duration::from_secs(3600))
+        .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Strict);
+    assert_eq!(
+        cookie.to_string(),
+        "session=abc; Path=/app; Domain=example.com; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Strict"
+    );
+    assert_eq!(Cookie::removal("session").to_string(), "session=; Max-Age=0");
+
+    // Nothing can add attributes of its own
+    let cookie = Cookie::new("a b;c", "x; Secure, \"100%\"").path("/;Domain=evil").domain("example.com\r\n");
+    assert_eq!(cookie.to_string(), "a%20b%3Bc=x%3B%20Secure%2C%20%22100%25%22; Path=/%3BDomain=evil; Domain=example.com%0D%0A");
+}
+
+#[test]
+fn test_response_builder() {
+    let response = Response::new(Status::Accepted)
+        .header("X-B", "1")
+        .header("X-A", "2")
+        .append_header("X-B", "3")
+        .header("x-a", "4")
+        .cookie(Cookie::new("a", "1"))
+        .cookie(Cookie::new("b", "2"));
+    assert_eq!(response.status, Status::Accepted);
+    assert_eq!(
+        String::from_utf8(response.head_bytes()).unwrap(),
+        "HTTP/1.1 202 Accepted\r\nX-B: 1\r\nx-a: 4\r\nX-B: 3\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n"
+    );
+
+    // A content type set first is kept
+    let response = Response::ok().content_type("application/problem+json").json(&HashMap::from([("title", "gone")]));
+    assert_eq!(response.headers.get("Content-Type"), Some("application/problem+json"));
+    assert!(matches!(&response.body, Body::Bytes(json) if json == br#"{"title":"gone"}"#));
+
+    // A map with non-string keys has no JSON form
+    let response = Response::ok().json(&HashMap::from([((1, 2), "pair")]));
+    assert_eq!(response.status, Status::InternalServerError);
+
+    let response = Response::ok().text("hi").status(Status::NotFound);
+    assert_eq!(response.status, Status::NotFound);
+    assert_eq!(response.headers.get("Content-Type"), Some("text/plain; charset=utf-8"));
+
+    // Header values that would split the head are never written
+    let response = Response::ok().header("X-Evil", "a\r\nSet-Cookie: x=1").header("X-Fine", "ok");
+    assert_eq!(String::from_utf8(response.head_bytes()).unwrap(), "HTTP/1.1 200 OK\r\nX-Fine: ok\r\n\r\n");
+
+    assert!(Response::ok().file("does/not/exist").is_err());
+    assert!(Response::ok().file(std::env::temp_dir()).is_err());
+}
+
+#[test]
+fn test_server_errors_keep_their_detail() {
+    let body = |response: Response| match response.body {
+        Body::Bytes(bytes) => String::from_utf8(bytes).unwrap(),
+        _ => panic!("expected a body in memory"),
+    };
+    let page = body(ParseError::Storage("/var/uploads: No space left on device".to_string()).into_response());
+    assert!(page.contains("Internal Server Error") && !page.contains("No space"), "{}", page);
+    let json = body(ApiError(ParseError::Handler("password rejected for db".to_string())).into_response());
+    assert!(json.contains("\"message\":\"Internal Server Error\"") && !json.contains("password"), "{}", json);
+
+    // A client's own mistake is explained to it
+    let json = body(ApiError(ParseError::UnprocessableContent("missing field `name`".to_string())).into_response());
+    assert!(json.contains("missing field `name`"), "{}", json);
+}
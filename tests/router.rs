@@ -0,0 +1,203 @@
+use http_serverrrrr::http_utils::middleware::Next;
+use http_serverrrrr::http_utils::query::QueryMap;
+use http_serverrrrr::http_utils::request::extractor::{Form, Json, Path, Query};
+use http_serverrrrr::http_utils::request::handler::{sync, HandlerResult, Request};
+use http_serverrrrr::http_utils::request::router::{RouteError, Router};
+use http_serverrrrr::http_utils::response::html_response;
+use http_serverrrrr::http_utils::status::Status;
+use http_serverrrrr::http_utils::types::{Body, UniversalBody};
+use serde::Deserialize;
+use std::net::SocketAddr;
+
+async fn dispatch(router: &Router, method: &str, path: &str) -> String {
+    let request = Request::new(method, path, SocketAddr::from(([127, 0, 0, 1], 0)));
+    send(router, request).await
+}
+
+async fn send(router: &Router, request: Request) -> String {
+    let response = router.route_request(request).await.unwrap();
+    let Body::Bytes(body) = &response.body else {
+        panic!("unexpected file or streaming response");
+    };
+    String::from_utf8([response.head_bytes(), body.clone()].concat()).unwrap()
+}
+
+async fn nested(req: Request) -> HandlerResult {
+    let message = format!("{}-{}", req.params["id"], req.params["post"]);
+    Ok(html_response(Status::Ok, "NESTED", &message))
+}
+
+fn echo_router() -> Router {
+    Router::new()
+        .get("/users/new", sync(|_| Ok(html_response(Status::Ok, "STATIC", "new"))))
+        .unwrap()
+        .get("/users/:id", sync(|req| Ok(html_response(Status::Ok, "PARAM", &req.params["id"]))))
+        .unwrap()
+        .get("/users/:id/posts/:post", nested)
+        .unwrap()
+        .get("/files/*rest", sync(|req| Ok(html_response(Status::Ok, "WILDCARD", &req.params["rest"]))))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_path_params_and_wildcards() {
+    let router = echo_router();
+
+    let response = dispatch(&router, "GET", "/users/42").await;
+    assert!(response.contains("<h1>PARAM</h1><p>42</p>"), "{}", response);
+
+    let response = dispatch(&router, "GET", "/users/new").await;
+    assert!(response.contains("<h1>STATIC</h1>"), "{}", response);
+
+    let response = dispatch(&router, "GET", "/users/7/posts/99/").await;
+    assert!(response.contains("<p>7-99</p>"), "{}", response);
+
+    let response = dispatch(&router, "GET", "/files/css/site/main.css").await;
+    assert!(response.contains("<p>css/site/main.css</p>"), "{}", response);
+
+    // Segments are decoded one by one, after the path is split
+    let response = dispatch(&router, "GET", "/users/a%2Fb/posts/caf%C3%A9").await;
+    assert!(response.contains("<p>a/b-café</p>"), "{}", response);
+
+    let response = dispatch(&router, "GET", "/files").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+    // HEAD finds the GET route and is listed wherever GET is
+    let response = dispatch(&router, "HEAD", "/users/42").await;
+    assert!(response.contains("<p>42</p>"), "{}", response);
+    let response = dispatch(&router, "DELETE", "/users/42").await;
+    assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"), "{}", response);
+}
+
+#[test]
+fn test_conflicting_routes_are_rejected() {
+    let ok = || sync(|_| Ok(html_response(Status::Ok, "OK", "OK")));
+
+    let duplicate = Router::new().get("/about", ok()).unwrap().get("/about/", ok());
+    assert_eq!(
+        duplicate.err(),
+        Some(RouteError::Conflict { method: "GET".to_string(), pattern: "/about/".to_string() })
+    );
+
+    let renamed_param = Router::new().get("/users/:id", ok()).unwrap().post("/users/:name", ok());
+    assert!(matches!(renamed_param, Err(RouteError::Conflict { .. })));
+
+    let same_pattern_other_method = Router::new().get("/users/:id", ok()).unwrap().delete("/users/:id", ok());
+    assert!(same_pattern_other_method.is_ok());
+
+    let wildcard_not_last = Router::new().get("/files/*rest/more", ok());
+    assert!(matches!(wildcard_not_last, Err(RouteError::InvalidPattern(_))));
+
+    let relative = Router::new().get("about", ok());
+    assert!(matches!(relative, Err(RouteError::InvalidPattern(_))));
+}
+
+async fn require_token(req: Request, next: Next) -> HandlerResult {
+    if req.headers.get("Authorization") != Some("Bearer secret") {
+        return Ok(html_response(Status::Unauthorized, "UNAUTHORIZED", "UNAUTHORIZED"));
+    }
+    next.run(req).await
+}
+
+async fn tag_request(mut req: Request, next: Next) -> HandlerResult {
+    req.headers.insert("X-User", "ferris");
+    let mut response = next.run(req).await?;
+    response.headers.insert("X-Served-By", "middleware");
+    Ok(response)
+}
+
+#[tokio::test]
+async fn test_middleware_chain() {
+    let admin = Router::new()
+        .get("/stats", sync(|req| Ok(html_response(Status::Ok, "STATS", req.headers.get("X-User").unwrap_or("nobody")))))
+        .unwrap()
+        .layer(require_token);
+    let router = Router::new()
+        .get("/", sync(|_| Ok(html_response(Status::Ok, "HOME", "HOME"))))
+        .unwrap()
+        .nest("/admin", admin)
+        .unwrap()
+        .layer(tag_request);
+
+    // Group middleware short-circuits, outer middleware still post-processes
+    let response = dispatch(&router, "GET", "/admin/stats").await;
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+    assert!(response.contains("X-Served-By: middleware\r\n"), "{}", response);
+
+    // Outer middleware changed the request before the handler saw it
+    let mut request = Request::new("GET", "/admin/stats", SocketAddr::from(([127, 0, 0, 1], 0)));
+    request.headers.insert("Authorization", "Bearer secret");
+    let response = send(&router, request).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("<p>ferris</p>"), "{}", response);
+
+    // Group middleware doesn't apply outside the group, router middleware covers 404s too
+    let response = dispatch(&router, "GET", "/").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    let response = dispatch(&router, "GET", "/missing").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    assert!(response.contains("X-Served-By: middleware\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn test_typed_extractors() {
+    #[derive(Deserialize)]
+    struct Ids {
+        id: u32,
+        post: u32,
+    }
+    #[derive(Deserialize)]
+    struct Paging {
+        page: u32,
+    }
+    #[derive(Deserialize, Debug)]
+    struct Comment {
+        author: String,
+        text: String,
+    }
+
+    async fn comment(req: Request) -> HandlerResult {
+        let Path(ids) = req.extract::<Path<Ids>>()?;
+        let Query(paging) = req.extract::<Query<Paging>>()?;
+        let Form(comment) = req.extract::<Form<Comment>>()?;
+        let message = format!("{}/{} page {}: {} says {}", ids.id, ids.post, paging.page, comment.author, comment.text);
+        Ok(html_response(Status::Ok, "COMMENT", &message))
+    }
+    let router = Router::new().post("/users/:id/posts/:post", comment).unwrap();
+    let request = |path: &str, query: &str, content_type: &str, body: &str| {
+        let mut request = Request::new("POST", path, SocketAddr::from(([127, 0, 0, 1], 0)));
+        request.query_map = QueryMap::parse(query).unwrap();
+        request.headers.insert("Content-Type", content_type);
+        request.body = match content_type {
+            "application/x-www-form-urlencoded" => UniversalBody::Form(QueryMap::parse(body).unwrap()),
+            _ => UniversalBody::Text(body.to_string()),
+        };
+        request
+    };
+    let form = "application/x-www-form-urlencoded";
+
+    let response = send(&router, request("/users/7/posts/9", "page=2", form, "author=ann&text=hi+there")).await;
+    assert!(response.contains("7/9 page 2: ann says hi there"), "{}", response);
+
+    let cases = [
+        (request("/users/x/posts/9", "page=2", form, "author=ann&text=hi"), Status::BadRequest),
+        (request("/users/7/posts/9", "page=two", form, "author=ann&text=hi"), Status::BadRequest),
+        (request("/users/7/posts/9", "page=2", form, "author=ann"), Status::UnprocessableContent),
+        (request("/users/7/posts/9", "page=2", "text/plain", "author=ann&text=hi"), Status::UnsupportedMediaType),
+    ];
+    for (request, status) in cases {
+        let Err(error) = router.route_request(request).await else {
+            panic!("expected a {:?}", status);
+        };
+        assert_eq!(error.status(), status, "{}", error);
+    }
+
+    // A JSON body of the wrong shape is a 422, any other body a 415
+    let mut request = Request::new("POST", "/", SocketAddr::from(([127, 0, 0, 1], 0)));
+    request.body = UniversalBody::Json(serde_json::json!({ "author": "ann" }));
+    let error = request.extract::<Json<Comment>>().unwrap_err();
+    assert_eq!(error.status(), Status::UnprocessableContent);
+    assert!(error.to_string().contains("missing field `text`"), "{}", error);
+    request.body = UniversalBody::Binary(vec![1, 2]);
+    assert_eq!(request.extract::<Json<Comment>>().unwrap_err().status(), Status::UnsupportedMediaType);
+}